@@ -2,11 +2,9 @@ use criterion::{Criterion, criterion_group, criterion_main};
 use rand_pcg::Pcg64Mcg;
 use robodoan::*;
 
-const NDIM: usize = 4;
-
 fn exec_moves(init_state: BlockSet, twists: &[Twist]) -> BlockSet {
     twists.iter().fold(init_state, |state, &twist| {
-        state.do_twist(twist, NDIM).unwrap()
+        state.do_twist(twist, &RUBIKS_4D).unwrap()
     })
 }
 
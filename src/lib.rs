@@ -1,10 +1,12 @@
 #[macro_use]
 mod macros;
 pub mod mc4d;
+pub mod notation;
 pub mod search;
 pub mod sim;
 pub mod stackvec;
 
+pub use notation::{Alg, AlgNode, ParseError};
 pub use sim::*;
 pub use stackvec::StackVec;
 
@@ -28,6 +30,12 @@ const MIN_SOLUTION_COUNT_DEPTH_2: usize = 5_000;
 const MIN_SOLUTION_COUNT_DEPTH_3: usize = 500;
 const MIN_SOLUTION_COUNT_DEPTH_4: usize = 50;
 
+/// File in which to cache the pattern database between runs.
+pub const PATTERN_DATABASE_PATH: &str = "pattern_db_4d.bin";
+
+/// File in which to save a checkpoint after each stage of a solve.
+pub const CHECKPOINT_PATH: &str = "checkpoint.bin";
+
 /// Whether to print 3D twist names when possible (e.g., `R` instead of `RO`).
 pub const USE_3D_TWIST_NAMES: bool = false;
 
@@ -56,8 +64,17 @@ impl Profile {
     }
 }
 
+/// Parses and expands an algorithm written in [`Alg`] notation.
+///
+/// # Panics
+///
+/// Panics if `s` isn't valid notation. See [`try_parse_twists()`] for a
+/// version that returns an error instead.
 pub fn parse_twists(s: &str) -> Vec<Twist> {
-    s.split_whitespace()
-        .map(|word| TWISTS_FROM_NAME[word])
-        .collect()
+    try_parse_twists(s).unwrap_or_else(|e| panic!("invalid algorithm {s:?}: {e}"))
+}
+/// Parses and expands an algorithm written in [`Alg`] notation, returning an
+/// error with the position of the first invalid token.
+pub fn try_parse_twists(s: &str) -> Result<Vec<Twist>, ParseError> {
+    Ok(s.parse::<Alg>()?.expand())
 }
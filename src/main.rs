@@ -6,15 +6,156 @@ use robodoan::*;
 fn main() -> Result<(), Box<dyn Error>> {
     let profile = Profile::Short;
 
-    if let Some(filename) = std::env::args().nth(1) {
-        let log_file_text = std::fs::read_to_string(&filename)?;
-        let scramble: mc4d::Mc4dScramble = log_file_text.parse()?;
-        println!("Loaded log file from {filename}");
+    let mut args = std::env::args().skip(1);
+    if let Some(filename) = args.next() {
+        if filename == "worker" {
+            let addr = args
+                .next()
+                .ok_or("missing address for worker to listen on")?;
+            let listener = std::net::TcpListener::bind(addr)?;
+            println!("Listening for shards on {}", listener.local_addr()?);
+            println!(
+                "WARNING: workers don't authenticate coordinators, so only listen on a \
+                 trusted network (e.g. 127.0.0.1:<port> for the same machine)"
+            );
+            run_worker(listener)?;
+            return Ok(());
+        }
+        if filename == "validate" {
+            let path = args.next().ok_or("missing sticker file to validate")?;
+            let stickers: StickerState = std::fs::read_to_string(path)?.parse()?;
+            let state = PuzzleState::try_from(&stickers)?;
+            let status = if state.is_solved() {
+                "solved"
+            } else if state.is_solvable() {
+                "not solved"
+            } else {
+                "unsolvable"
+            };
+            println!("Valid sticker state ({status})");
+            return Ok(());
+        }
+        if filename == "analyze" {
+            let alg: Alg = args.join(" ").parse()?;
+            let twists = alg.expand();
+            if twists.is_empty() {
+                return Err("missing twists to analyze".into());
+            }
+            print!("{}", AlgorithmAnalysis::new(&twists));
+            return Ok(());
+        }
+        if filename == "random-state" {
+            let state = PuzzleState::random(&mut rand::rng());
+            println!("Random state:\n{}", StickerState::from(&state));
+            let solve_twists = robodoan::Solver::from_state(profile, state).solve();
+            // The solution only solves F2L, so its inverse reaches a state
+            // with the same F2L pieces but a different last layer.
+            let scramble = solve_twists.iter().rev().map(|t| t.inv()).collect_vec();
+            println!();
+            println!("F2L scramble: {}", scramble.iter().join(" "));
+            let mc4d_scramble =
+                mc4d::Mc4dScramble::from_scramble(&scramble).ok_or("no MC4D twist")?;
+            std::fs::write("out.log", mc4d_scramble.to_string(false, vec![]))?;
+            println!("Saved scramble to out.log");
+            return Ok(());
+        }
+
+        let mut checkpoint = false;
+        let mut resume = false;
+        let mut workers = vec![];
+        let mut thread_count = None;
+        let mut deterministic = false;
+        let mut pareto_front = false;
+        let mut slice_twists = false;
+        let mut rotations = false;
+        let mut pattern_database = false;
+        let mut sticker_input = false;
+        let mut metric = Metric::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--checkpoint" => checkpoint = true,
+                "--resume" => {
+                    checkpoint = true;
+                    resume = true;
+                }
+                "--workers" => {
+                    let addrs = args.next().ok_or("missing worker addresses")?;
+                    workers.extend(addrs.split(',').map(str::to_owned));
+                }
+                "--deterministic" => deterministic = true,
+                "--pareto" => pareto_front = true,
+                "--slice-twists" => slice_twists = true,
+                "--rotations" => rotations = true,
+                "--pattern-database" => pattern_database = true,
+                "--stickers" => sticker_input = true,
+                "--metric" => metric = args.next().ok_or("missing metric")?.parse()?,
+                "--cost-model" => {
+                    let path = args.next().ok_or("missing cost model file")?;
+                    let cost_model: CostModel = std::fs::read_to_string(path)?.parse()?;
+                    metric = Metric::Cost(cost_model.intern()?);
+                }
+                "--threads" => {
+                    let n = args.next().ok_or("missing thread count")?;
+                    thread_count = Some(n.parse()?);
+                }
+                _ => return Err(format!("unknown argument {arg:?}").into()),
+            }
+        }
+        let file_text = std::fs::read_to_string(&filename)?;
+        let (mc4d_scramble, scramble, initial_state) = if sticker_input {
+            let stickers: StickerState = file_text.parse()?;
+            println!("Loaded stickers from {filename}");
+            (None, vec![], PuzzleState::try_from(&stickers)?)
+        } else {
+            let mc4d_scramble: mc4d::Mc4dScramble = file_text.parse()?;
+            println!("Loaded log file from {filename}");
+            let n = mc4d_scramble.layer_count();
+            let scramble = mc4d_scramble
+                .scramble()
+                .ok_or_else(|| format!("only 3x3x3x3 puzzles can be solved, not {n}x{n}x{n}x{n}"))?
+                .to_vec();
+            (Some(mc4d_scramble), scramble, PuzzleState::default())
+        };
+        let pattern_database = pattern_database.then(|| {
+            let (db, source) = PatternDatabase::load_or_build(&RUBIKS_4D, PATTERN_DATABASE_PATH);
+            println!("Pattern database {source} ({PATTERN_DATABASE_PATH})");
+            &*Box::leak(Box::new(db))
+        });
+        let solver = if sticker_input {
+            robodoan::Solver::from_state(profile, initial_state)
+        } else {
+            robodoan::Solver::new(profile, scramble)
+        };
+        let mut solver = solver
+            .with_deterministic(deterministic)
+            .with_metric(metric)
+            .with_pareto_front(pareto_front)
+            .with_slice_twists(slice_twists)
+            .with_rotations(rotations)
+            .with_pattern_database(pattern_database)
+            .with_workers(&workers)?;
+        if let Some(thread_count) = thread_count {
+            solver = solver.with_thread_count(thread_count)?;
+        }
+        if resume {
+            solver = solver
+                .resume(CHECKPOINT_PATH)
+                .map_err(|e| format!("unable to resume from {CHECKPOINT_PATH}: {e}"))?;
+            println!("Loaded checkpoint from {CHECKPOINT_PATH}");
+        }
+        if checkpoint {
+            solver = solver.with_checkpoint_path(CHECKPOINT_PATH);
+        }
+        if !workers.is_empty() {
+            println!("Connected to {} workers", workers.len());
+        }
         println!();
         // let (solve_twists, _elapsed_time) = search_4d(scramble.scramble());
-        let solve_twists = robodoan::Solver::new(profile, scramble.scramble()).solve();
+        let solve_twists = solver.solve();
         println!();
-        std::fs::write("out.log", scramble.to_string(false, solve_twists))?;
+        if let Some(mc4d_scramble) = mc4d_scramble {
+            std::fs::write("out.log", mc4d_scramble.to_string(false, solve_twists))?;
+        }
         return Ok(());
     }
 
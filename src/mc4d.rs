@@ -7,20 +7,31 @@ use std::str::FromStr;
 use cgmath::vec4;
 use itertools::Itertools;
 
-use crate::StackVec;
 use crate::sim::*;
+use crate::{ParseError, StackVec};
 
 const MAGIC_STRING: &str = "MagicCube4D";
 const LOG_VERSION: &str = "3";
 const RUBIKS_4D_SCHALFLI_SYMBOL: &str = "{4,3,3}";
-const LAYER_COUNT: &str = "3";
 
+// Scramble states in the log file header.
+const FULLY_SCRAMBLED_STATE: &str = "2";
+const SOLVED_STATE: &str = "3";
+
+const DEFAULT_VIEW_MATRIX: &str = "1 0 0 0\n0 1 0 0\n0 0 1 0\n0 0 0 1";
+
+/// Maximum number of layers along each axis.
+const MAX_LAYER_COUNT: u8 = TwistLayers::MAX_LAYER_COUNT;
+
+/// Twist done by clicking each sticker, indexed by layer count and then by
+/// sticker ID.
 #[static_init::dynamic]
-static TWIST_FROM_MC4D_STICKER_ID: Vec<Option<Twist>> = mc4d_twist_order();
+static TWISTS_FROM_MC4D_STICKER_ID: Vec<Vec<Option<Twist>>> =
+    (0..=MAX_LAYER_COUNT).map(mc4d_twist_order).collect();
 
 #[static_init::dynamic]
 static TWIST_TO_MC4D: HashMap<Twist, Mc4dTwist> = {
-    let mc4d_twists = TWIST_FROM_MC4D_STICKER_ID
+    let mc4d_twists = TWISTS_FROM_MC4D_STICKER_ID[3]
         .iter()
         .enumerate()
         .filter_map(|(i, &twist)| Some((i, twist?)));
@@ -33,16 +44,72 @@ static TWIST_TO_MC4D: HashMap<Twist, Mc4dTwist> = {
     // Add 180-degree ridge turns
     for (i, twist) in mc4d_twists {
         twist_to_mc4d
-            .entry(Twist {
-                grip: twist.grip,
-                transform: twist.transform * twist.transform,
-            })
+            .entry(Twist::new(twist.grip(), twist.transform * twist.transform))
             .or_insert(Mc4dTwist::new(i, 2, 1));
     }
 
     twist_to_mc4d
 };
 
+/// Kind of click used to do a twist in MC4D, which depends on the piece that
+/// the clicked sticker is on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ClickType {
+    /// Click on a ridge sticker, for a 90° twist.
+    Ridge,
+    /// Click with multiplier 2 on a ridge sticker, for a 180° twist.
+    DoubleRidge,
+    /// Click on an edge sticker, for a 180° twist.
+    Edge,
+    /// Click on a corner sticker, for a 120° twist.
+    Corner,
+}
+impl fmt::Display for ClickType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClickType::Ridge => write!(f, "ridge"),
+            ClickType::DoubleRidge => write!(f, "ridge2"),
+            ClickType::Edge => write!(f, "edge"),
+            ClickType::Corner => write!(f, "corner"),
+        }
+    }
+}
+impl FromStr for ClickType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ridge" => Ok(ClickType::Ridge),
+            "ridge2" => Ok(ClickType::DoubleRidge),
+            "edge" => Ok(ClickType::Edge),
+            "corner" => Ok(ClickType::Corner),
+            _ => Err(()),
+        }
+    }
+}
+impl ClickType {
+    pub const ALL: [ClickType; 4] = [
+        ClickType::Ridge,
+        ClickType::DoubleRidge,
+        ClickType::Edge,
+        ClickType::Corner,
+    ];
+
+    /// Returns the kind of click used to do `twist`, or `None` if `twist` isn't
+    /// a single click. The layers of `twist` don't matter.
+    pub fn of(twist: Twist) -> Option<Self> {
+        let mc4d_twist = TWIST_TO_MC4D.get(&twist.with_layers(TwistLayers::OUTER))?;
+        // Stickers on each face are ordered corners, edges, ridges, center.
+        match (mc4d_twist.sticker % 27, mc4d_twist.multiplier) {
+            (0..8, 1) => Some(ClickType::Corner),
+            (8..20, 1) => Some(ClickType::Edge),
+            (20..26, 1) => Some(ClickType::Ridge),
+            (20..26, 2) => Some(ClickType::DoubleRidge),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 struct Mc4dTwist {
     sticker: usize,
@@ -60,15 +127,25 @@ impl fmt::Display for Mc4dTwist {
     }
 }
 impl FromStr for Mc4dTwist {
-    type Err = ();
+    type Err = String;
 
+    /// Parses a twist in the form `sticker,multiplier,layer_mask`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let [sticker_str, multiplier_str, layer_mask_str] =
-            s.split(",").collect_array().ok_or(())?;
+        let [sticker_str, multiplier_str, layer_mask_str] = s
+            .split(",")
+            .collect_array()
+            .ok_or("expected `sticker,multiplier,layer_mask`")?;
+        let sticker = sticker_str
+            .parse()
+            .map_err(|_| format!("invalid sticker ID `{sticker_str}`"))?;
         Ok(Self {
-            sticker: sticker_str.parse().map_err(|_| ())?,
-            multiplier: multiplier_str.parse().map_err(|_| ())?,
-            layer_mask: layer_mask_str.parse().map_err(|_| ())?,
+            sticker,
+            multiplier: multiplier_str
+                .parse()
+                .map_err(|_| format!("invalid multiplier `{multiplier_str}`"))?,
+            layer_mask: layer_mask_str
+                .parse()
+                .map_err(|_| format!("invalid layer mask `{layer_mask_str}`"))?,
         })
     }
 }
@@ -80,9 +157,20 @@ impl Mc4dTwist {
             layer_mask,
         }
     }
-    pub fn to_layered_twist(self) -> LayeredTwist {
-        let Twist { grip, transform: t } =
-            TWIST_FROM_MC4D_STICKER_ID[self.sticker].expect("bad MC4D twist");
+    /// Returns the twist for a 3x3x3x3 twist from the log file.
+    fn from_twist(twist: Twist) -> Option<Self> {
+        let outer_twist = TWIST_TO_MC4D.get(&twist.with_layers(TwistLayers::OUTER))?;
+        Some(Self {
+            layer_mask: twist.layers().mask(),
+            ..*outer_twist
+        })
+    }
+
+    /// Returns the twist on a puzzle with `layer_count` layers, or `None` if
+    /// the sticker can't be clicked to twist.
+    fn to_twist(self, layer_count: u8) -> Option<Twist> {
+        let twist = twist_from_mc4d_sticker(layer_count, self.sticker)?;
+        let (grip, t) = (twist.grip(), twist.transform);
 
         let transform = std::iter::repeat_n(
             if self.multiplier < 0 { t.inv() } else { t },
@@ -90,83 +178,89 @@ impl Mc4dTwist {
         )
         .fold(IDENT, |a, b| a * b);
 
-        LayeredTwist {
-            twist: Twist { grip, transform },
-            layer_mask: self.layer_mask,
-        }
+        let layers = TwistLayers::new(self.layer_mask).expect("empty layer mask");
+        Some(Twist::new(grip, transform).with_layers(layers))
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
-struct LayeredTwist {
-    twist: Twist,
-    layer_mask: u8,
+/// Returns the outer-layer twist done by clicking `sticker` on a puzzle with
+/// `layer_count` layers, or `None` if the sticker can't be clicked to twist.
+fn twist_from_mc4d_sticker(layer_count: u8, sticker: usize) -> Option<Twist> {
+    (TWISTS_FROM_MC4D_STICKER_ID[layer_count as usize])
+        .get(sticker)
+        .copied()
+        .flatten()
 }
-impl LayeredTwist {
-    pub fn to_twists(self, puzzle_offset: &mut ElemId) -> StackVec<Twist, 2> {
-        let Self {
-            mut twist,
-            mut layer_mask,
-        } = self;
-
-        // middle slice
-        let middle_slice = layer_mask & 0b010 != 0;
-        if middle_slice {
-            // Rotate the whole puzzle
-            *puzzle_offset = *puzzle_offset * self.twist.transform.inv();
-
-            // Do the opposite twist on the other layers
-            twist = twist.inv();
-            layer_mask ^= 0b111;
-        }
 
-        let mut ret = StackVec::new();
+/// Returns outer-layer twists on a 3x3x3x3 that are equivalent to `twist`, and
+/// updates `puzzle_offset` to account for rotations of the whole puzzle.
+fn to_twists(twist: Twist, puzzle_offset: &mut ElemId) -> StackVec<Twist, 2> {
+    let mut layer_mask = twist.layers().mask();
+    let mut twist = twist.with_layers(TwistLayers::OUTER);
+
+    // middle slice
+    let middle_slice = layer_mask & 0b010 != 0;
+    if middle_slice {
+        // Rotate the whole puzzle
+        *puzzle_offset = *puzzle_offset * twist.transform.inv();
+
+        // Do the opposite twist on the other layers
+        twist = twist.inv();
+        layer_mask ^= 0b111;
+    }
 
-        // original layer
-        if layer_mask & 0b001 != 0 {
-            ret = ret.push(twist).unwrap();
-        }
+    let mut ret = StackVec::new();
 
-        // opposite layer
-        if layer_mask & 0b100 != 0 {
-            let twist_on_opposite = Twist {
-                grip: twist.grip.opposite(),
-                transform: twist.transform,
-            };
-            ret = ret.push(twist_on_opposite).unwrap();
-        }
+    // original layer
+    if layer_mask & 0b001 != 0 {
+        ret = ret.push(twist).unwrap();
+    }
 
-        ret.map(|t| puzzle_offset.transform(t))
+    // opposite layer
+    if layer_mask & 0b100 != 0 {
+        let twist_on_opposite = Twist::new(twist.grip().opposite(), twist.transform);
+        ret = ret.push(twist_on_opposite).unwrap();
     }
+
+    ret.map(|t| puzzle_offset.transform(t))
 }
 
 pub struct Mc4dScramble {
     scramble_state: String,
     view_matrix: String,
     mc4d_scramble: Vec<Mc4dTwist>,
+    layer_count: u8,
 
-    scramble: Vec<Twist>,
+    /// Scramble as twists on a 3x3x3x3, or `None` for other layer counts.
+    scramble: Option<Vec<Twist>>,
     puzzle_offset_from_scramble: ElemId,
 }
 impl FromStr for Mc4dScramble {
-    type Err = &'static str;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s;
         let s = match s.rsplit_once("m|") {
             Some((before_boundary, _after_boundary)) => before_boundary,
             None => s,
         };
 
         let mut lines = s.lines();
-        let header = lines.next().ok_or("missing header")?;
+        let header = lines
+            .next()
+            .ok_or_else(|| ParseError::new(source, 0, "", "missing header"))?;
         let segments = header.split_whitespace().collect_vec();
 
         if segments.len() != 6 || segments[0] != MAGIC_STRING {
-            return Err("bad header");
+            return Err(ParseError::at_token(source, header, "bad header"));
         }
 
         if segments[1] != LOG_VERSION {
-            return Err("unsupported log version");
+            return Err(ParseError::at_token(
+                source,
+                segments[1],
+                "unsupported log version",
+            ));
         }
 
         let scramble_state = segments[2].to_string();
@@ -174,14 +268,34 @@ impl FromStr for Mc4dScramble {
         // Ignore move count (`segments[3]`)
 
         // Check puzzle Schlafli symbol and edge length
-        if segments[4] != RUBIKS_4D_SCHALFLI_SYMBOL || segments[5] != LAYER_COUNT {
-            return Err("unsupport puzzle; only 3x3x3x3 is supported");
+        if segments[4] != RUBIKS_4D_SCHALFLI_SYMBOL {
+            return Err(ParseError::at_token(
+                source,
+                segments[4],
+                "unsupported puzzle; only n x n x n x n is supported",
+            ));
         }
+        let layer_count = segments[5]
+            .parse()
+            .ok()
+            .filter(|n| (1..=MAX_LAYER_COUNT).contains(n))
+            .ok_or_else(|| ParseError::at_token(source, segments[5], "unsupported layer count"))?;
 
         let view_matrix = (&mut lines).take(4).join("\n");
 
-        if lines.next() != Some("*") {
-            return Err("missing `*` separator");
+        match lines.next() {
+            Some("*") => (),
+            Some(line) => {
+                return Err(ParseError::at_token(source, line, "missing `*` separator"));
+            }
+            None => {
+                return Err(ParseError::new(
+                    source,
+                    s.len(),
+                    "",
+                    "missing `*` separator",
+                ));
+            }
         }
 
         let mut mc4d_scramble = vec![];
@@ -191,33 +305,103 @@ impl FromStr for Mc4dScramble {
                 .map(|s| s.trim_end_matches('.').trim())
                 .filter(|s| !s.is_empty())
             {
-                mc4d_scramble
-                    .push(Mc4dTwist::from_str(move_str).map_err(|()| "error parsing move")?);
+                let mc4d_twist = Mc4dTwist::from_str(move_str).map_err(|e| {
+                    ParseError::at_token(
+                        source,
+                        move_str,
+                        format!("invalid move `{move_str}`: {e}"),
+                    )
+                })?;
+                let sticker = mc4d_twist.sticker;
+                if twist_from_mc4d_sticker(layer_count, sticker).is_none() {
+                    return Err(ParseError::at_token(
+                        source,
+                        move_str,
+                        format!(
+                            "invalid move `{move_str}`: sticker {sticker} can't be clicked to twist"
+                        ),
+                    ));
+                }
+                if mc4d_twist.layer_mask == 0
+                    || u32::from(mc4d_twist.layer_mask) >> layer_count != 0
+                {
+                    return Err(ParseError::at_token(
+                        source,
+                        move_str,
+                        format!("layer mask of move `{move_str}` is out of range"),
+                    ));
+                }
+                mc4d_scramble.push(mc4d_twist);
             }
         }
 
+        Ok(Self::new(
+            scramble_state,
+            view_matrix,
+            mc4d_scramble,
+            layer_count,
+        ))
+    }
+}
+impl Mc4dScramble {
+    /// Returns a log file for a puzzle with `layer_count` layers, where every
+    /// twist in `mc4d_scramble` has a clickable sticker and a valid layer mask.
+    fn new(
+        scramble_state: String,
+        view_matrix: String,
+        mc4d_scramble: Vec<Mc4dTwist>,
+        layer_count: u8,
+    ) -> Self {
         let mut scramble = vec![];
         let mut puzzle_offset = IDENT;
-        for &mc4d_twist in &mc4d_scramble {
-            scramble.extend(mc4d_twist.to_layered_twist().to_twists(&mut puzzle_offset));
+        if layer_count == 3 {
+            for &mc4d_twist in &mc4d_scramble {
+                let twist = mc4d_twist.to_twist(3).expect("invalid MC4D twist");
+                scramble.extend(to_twists(twist, &mut puzzle_offset));
+            }
         }
 
-        Ok(Self {
+        Self {
             scramble_state,
             view_matrix,
             mc4d_scramble,
+            layer_count,
 
-            scramble,
+            scramble: (layer_count == 3).then_some(scramble),
             puzzle_offset_from_scramble: puzzle_offset,
-        })
+        }
     }
-}
-impl Mc4dScramble {
+
+    /// Returns a fully scrambled 3x3x3x3 log file with `scramble`, viewed from
+    /// the default angle, or `None` if a twist can't be done in MC4D.
+    pub fn from_scramble(scramble: &[Twist]) -> Option<Self> {
+        let mc4d_scramble = (scramble.iter())
+            .map(|&twist| Mc4dTwist::from_twist(twist))
+            .collect::<Option<_>>()?;
+        Some(Self::new(
+            FULLY_SCRAMBLED_STATE.to_string(),
+            DEFAULT_VIEW_MATRIX.to_string(),
+            mc4d_scramble,
+            3,
+        ))
+    }
+
+    /// Returns a log file with `solve_twists` after the scramble.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the puzzle isn't a 3x3x3x3.
     pub fn to_string(&self, solved: bool, solve_twists: Vec<Twist>) -> String {
+        assert_eq!(self.layer_count, 3, "only 3x3x3x3 solutions can be saved");
         let move_count = solve_twists.len();
-        let state = if solved { "3" } else { &self.scramble_state };
+        let state = if solved {
+            SOLVED_STATE
+        } else {
+            &self.scramble_state
+        };
+        let layer_count = self.layer_count;
         let mut log_file_string = format!(
-            "{MAGIC_STRING} {LOG_VERSION} {state} {move_count} {RUBIKS_4D_SCHALFLI_SYMBOL} {LAYER_COUNT}\n"
+            "{MAGIC_STRING} {LOG_VERSION} {state} {move_count} {RUBIKS_4D_SCHALFLI_SYMBOL} {layer_count}\n"
         );
         log_file_string += &self.view_matrix;
         log_file_string += "\n*";
@@ -238,20 +422,34 @@ impl Mc4dScramble {
         let offset = self.puzzle_offset_from_scramble.inv();
         for twist in solve_twists {
             add_twist_sep(&mut log_file_string);
-            log_file_string += &TWIST_TO_MC4D[&offset.transform(twist)].to_string();
+            let mc4d_twist = Mc4dTwist::from_twist(offset.transform(twist));
+            log_file_string += &mc4d_twist.expect("no MC4D twist").to_string();
         }
 
         log_file_string + "."
     }
 
-    pub fn scramble(&self) -> &[Twist] {
-        &self.scramble
+    /// Returns the scramble as twists on a 3x3x3x3, or `None` if the puzzle
+    /// has a different number of layers.
+    pub fn scramble(&self) -> Option<&[Twist]> {
+        self.scramble.as_deref()
+    }
+
+    /// Returns the number of layers along each axis of the puzzle.
+    pub fn layer_count(&self) -> u8 {
+        self.layer_count
     }
 }
 
 const UNIT_VECTORS: [Vec4; 4] = [X, Y, Z, W];
 
-fn mc4d_twist_order() -> Vec<Option<Twist>> {
+/// Returns the twist done by clicking each sticker on a puzzle with
+/// `layer_count` layers.
+///
+/// Each face has `layer_count^3` stickers. A sticker twists the grip that its
+/// face is on, like the sticker at the same place on a 3x3x3x3, where only the
+/// outermost stickers along each axis are away from the middle.
+fn mc4d_twist_order(layer_count: u8) -> Vec<Option<Twist>> {
     let seed_twists = [
         ((I, vec4(-1, 0, 0, 0)), TWISTS_FROM_NAME["IR"]),
         ((I, vec4(-1, -1, 0, 0)), TWISTS_FROM_NAME["IUR"]),
@@ -264,6 +462,15 @@ fn mc4d_twist_order() -> Vec<Option<Twist>> {
             })
             .collect();
 
+    // Position of each layer relative to the middle of a 3x3x3x3.
+    let n = layer_count as i8;
+    let layer_positions = (0..n).map(move |i| match i {
+        _ if n == 1 => 0,
+        0 => -1,
+        _ if i == n - 1 => 1,
+        _ => 0,
+    });
+
     // ported from HSC1
     [I, B, D, L, R, U, F, O]
         .into_iter()
@@ -277,13 +484,17 @@ fn mc4d_twist_order() -> Vec<Option<Twist>> {
             let mc4d_basis_2 = UNIT_VECTORS[basis[1].axis()];
             let mc4d_basis_3 = UNIT_VECTORS[basis[2].axis()];
 
-            let piece_locations =
-                itertools::iproduct!([-1, 0, 1], [-1, 0, 1], [-1, 0, 1]).map(|(x, y, z)| [x, y, z]);
+            let piece_locations = itertools::iproduct!(
+                layer_positions.clone(),
+                layer_positions.clone(),
+                layer_positions.clone()
+            )
+            .map(|(x, y, z)| [x, y, z]);
             let corners = piece_locations.clone().filter(|v| abs_sum(v) == 3);
             let edges = piece_locations.clone().filter(|v| abs_sum(v) == 2);
-            let ridges = piece_locations.filter(|v| abs_sum(v) == 1);
-            let center = std::iter::once([0, 0, 0]);
-            let mc4d_order_piece_locations = corners.chain(edges).chain(ridges).chain(center);
+            let ridges = piece_locations.clone().filter(|v| abs_sum(v) == 1);
+            let centers = piece_locations.filter(|v| abs_sum(v) == 0);
+            let mc4d_order_piece_locations = corners.chain(edges).chain(ridges).chain(centers);
 
             mc4d_order_piece_locations.map(move |mc4d_coords_of_sticker_within_face: [i8; 3]| {
                 let fixed_vector = ZERO
@@ -315,3 +526,144 @@ fn basis_faces(g: GripId) -> [GripId; 3] {
         if g.axis() == 2 { w } else { F },
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn log_file(moves: &str) -> String {
+        log_file_with_layer_count(3, moves)
+    }
+
+    fn log_file_with_layer_count(layer_count: u8, moves: &str) -> String {
+        let header =
+            format!("{MAGIC_STRING} {LOG_VERSION} 2 0 {RUBIKS_4D_SCHALFLI_SYMBOL} {layer_count}");
+        format!("{header}\n1 0 0 0\n0 1 0 0\n0 0 1 0\n0 0 0 1\n*\n{moves}\nm| 1,1,1.")
+    }
+
+    /// Returns the twists in the log file, in the same frame as the log file,
+    /// where bit `i` of each layer mask is the `i`th layer counting inward
+    /// from its grip.
+    fn layered_twists(scramble: &Mc4dScramble) -> Vec<Twist> {
+        (scramble.mc4d_scramble.iter())
+            .map(|t| t.to_twist(scramble.layer_count).unwrap())
+            .collect()
+    }
+
+    fn parse_err(log_file: &str) -> ParseError {
+        log_file
+            .parse::<Mc4dScramble>()
+            .err()
+            .expect("expected an error")
+    }
+
+    #[test]
+    fn test_mc4d_parse_errors() {
+        let scramble: Mc4dScramble = log_file("1,1,1 62,-1,1\n57,1,3.").parse().unwrap();
+        assert_eq!(scramble.mc4d_scramble.len(), 3);
+
+        let err = parse_err(&log_file("1,1,1 62,x,1"));
+        assert_eq!((err.line, err.column, err.token.as_str()), (7, 7, "62,x,1"));
+        assert_eq!(err.message, "invalid move `62,x,1`: invalid multiplier `x`");
+
+        let err = parse_err(&log_file("1,1,1\n26,1,1"));
+        assert_eq!((err.line, err.column), (8, 1));
+        assert!(err.message.contains("sticker 26"), "{err}");
+
+        let err = parse_err(&log_file("1,1,8"));
+        assert!(err.message.contains("layer mask"), "{err}");
+
+        let err = log_file("").replace("{4,3,3}", "{3,3,3}");
+        let err = parse_err(&err);
+        assert_eq!(
+            (err.line, err.column, err.token.as_str()),
+            (1, 19, "{3,3,3}")
+        );
+    }
+
+    #[test]
+    fn test_mc4d_from_scramble() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let twists = crate::RUBIKS_4D.random_moves(&mut rng, 30);
+        let log = Mc4dScramble::from_scramble(&twists).unwrap();
+        let reparsed: Mc4dScramble = log.to_string(false, vec![]).parse().unwrap();
+        assert_eq!(reparsed.scramble_state, FULLY_SCRAMBLED_STATE);
+        assert_eq!(reparsed.scramble(), Some(&*twists));
+    }
+
+    #[test]
+    fn test_mc4d_other_layer_counts() {
+        let click_type = |twist: Twist| ClickType::of(twist.with_layers(TwistLayers::OUTER));
+
+        // Every sticker on a 2x2x2x2 is a corner, and each face has 8.
+        let log = log_file_with_layer_count(2, "0,1,1 8,-1,2 63,1,3.");
+        let scramble: Mc4dScramble = log.parse().unwrap();
+        assert_eq!(scramble.layer_count(), 2);
+        assert!(scramble.scramble().is_none());
+        let twists = layered_twists(&scramble);
+        assert_eq!(twists.iter().map(|t| t.grip()).collect_vec(), [I, B, O]);
+        assert!(
+            twists
+                .iter()
+                .all(|&t| click_type(t) == Some(ClickType::Corner))
+        );
+        assert_eq!(twists[1].layers(), TwistLayers::new(2).unwrap());
+        assert_eq!(twists[0], TWISTS_FROM_MC4D_STICKER_ID[3][0].unwrap());
+        assert_eq!(
+            twists[1],
+            TWISTS_FROM_MC4D_STICKER_ID[3][27]
+                .unwrap()
+                .inv()
+                .with_layers(TwistLayers::new(2).unwrap())
+        );
+        assert!(
+            parse_err(&log_file_with_layer_count(2, "64,1,1"))
+                .message
+                .contains("sticker 64")
+        );
+        assert!(
+            parse_err(&log_file_with_layer_count(2, "0,1,4"))
+                .message
+                .contains("layer mask")
+        );
+
+        // A 4x4x4x4 has 64 stickers on each face: 8 corners, 24 edges, 24
+        // ridges, and 8 centers that can't be clicked.
+        let log = log_file_with_layer_count(4, "300,1,1 0,1,15 455,1,8.");
+        let scramble: Mc4dScramble = log.parse().unwrap();
+        let twists = layered_twists(&scramble);
+        assert_eq!(twists.iter().map(|t| t.grip()).collect_vec(), [R, I, O]);
+        assert_eq!(click_type(twists[0]), Some(ClickType::Ridge));
+        assert_eq!(click_type(twists[1]), Some(ClickType::Corner));
+        assert_eq!(twists[1].layers(), TwistLayers::new(0b1111).unwrap());
+        assert_eq!(twists[2].layers(), TwistLayers::new(0b1000).unwrap());
+        let clickable = (0..8 * 64).filter(|&i| twist_from_mc4d_sticker(4, i).is_some());
+        assert_eq!(clickable.count(), 8 * 56);
+        for face_start in (0..8 * 64).step_by(64) {
+            for (i, click_type) in [
+                (0, ClickType::Corner),
+                (8, ClickType::Edge),
+                (32, ClickType::Ridge),
+            ] {
+                let twist = twist_from_mc4d_sticker(4, face_start + i).unwrap();
+                assert_eq!(ClickType::of(twist), Some(click_type));
+            }
+            assert!(twist_from_mc4d_sticker(4, face_start + 56).is_none());
+        }
+        assert!(
+            parse_err(&log_file_with_layer_count(4, "60,1,1"))
+                .message
+                .contains("sticker 60")
+        );
+        assert!(
+            parse_err(&log_file_with_layer_count(4, "0,1,16"))
+                .message
+                .contains("layer mask")
+        );
+
+        // The 3x3x3x3 table is unchanged.
+        assert_eq!(TWISTS_FROM_MC4D_STICKER_ID[3].len(), 8 * 27);
+    }
+}
@@ -0,0 +1,481 @@
+//! Algorithm notation with commutators, conjugates, and repeated groups
+
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::{TWISTS_FROM_NAME, Twist, similar_twist_names};
+
+/// Characters with special meaning in algorithm notation, which can't appear
+/// in twist names.
+const SPECIAL_CHARS: &[char] = &['(', ')', '[', ']', ',', ':'];
+
+/// Sequence of twists written in algorithm notation, preserving its
+/// structure so that it can be printed the same way it was written.
+///
+/// Besides twist names separated by whitespace, the notation supports:
+///
+/// - `(A)` to group a sequence
+/// - `[A, B]` for the commutator `A B A' B'`
+/// - `[A: B]` for the conjugate `A B A'`
+/// - `X'` to invert a group, commutator, or conjugate
+/// - `X3` to repeat a group, commutator, or conjugate
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Alg(pub Vec<AlgNode>);
+
+/// Element of an [`Alg`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AlgNode {
+    /// Single twist.
+    Twist(Twist),
+    /// Parenthesized sequence `(A)`.
+    Group(Alg),
+    /// Commutator `[A, B]`, which is `A B A' B'`.
+    Commutator(Alg, Alg),
+    /// Conjugate `[A: B]`, which is `A B A'`.
+    Conjugate(Alg, Alg),
+    /// Inverse `X'`.
+    Inverse(Box<AlgNode>),
+    /// Repetition `Xn`.
+    Repeat(Box<AlgNode>, u32),
+}
+
+impl Alg {
+    /// Maximum number of twists that a parsed algorithm can expand to, so that
+    /// large repeat counts can't use up all the memory.
+    pub const MAX_EXPANDED_LEN: usize = 1_000_000;
+
+    /// Returns the number of twists in the expanded algorithm, saturating at
+    /// `usize::MAX`.
+    pub fn expanded_len(&self) -> usize {
+        (self.0.iter()).fold(0, |total, node| total.saturating_add(node.expanded_len()))
+    }
+
+    /// Returns the twists in the algorithm, with every commutator, conjugate,
+    /// inverse, and repetition expanded.
+    pub fn expand(&self) -> Vec<Twist> {
+        let mut ret = vec![];
+        self.expand_into(&mut ret);
+        ret
+    }
+    fn expand_into(&self, out: &mut Vec<Twist>) {
+        for node in &self.0 {
+            node.expand_into(out);
+        }
+    }
+}
+impl AlgNode {
+    /// Returns the number of twists in the expanded node, saturating at
+    /// `usize::MAX`.
+    pub fn expanded_len(&self) -> usize {
+        match self {
+            AlgNode::Twist(_) => 1,
+            AlgNode::Group(a) => a.expanded_len(),
+            AlgNode::Commutator(a, b) => {
+                (a.expanded_len().saturating_add(b.expanded_len())).saturating_mul(2)
+            }
+            AlgNode::Conjugate(a, b) => {
+                (a.expanded_len().saturating_mul(2)).saturating_add(b.expanded_len())
+            }
+            AlgNode::Inverse(node) => node.expanded_len(),
+            AlgNode::Repeat(node, count) => node.expanded_len().saturating_mul(*count as usize),
+        }
+    }
+
+    /// Returns the twists in the node, with every commutator, conjugate,
+    /// inverse, and repetition expanded.
+    pub fn expand(&self) -> Vec<Twist> {
+        let mut ret = vec![];
+        self.expand_into(&mut ret);
+        ret
+    }
+    fn expand_into(&self, out: &mut Vec<Twist>) {
+        match self {
+            AlgNode::Twist(twist) => out.push(*twist),
+            AlgNode::Group(a) => a.expand_into(out),
+            AlgNode::Commutator(a, b) => {
+                let (a, b) = (a.expand(), b.expand());
+                out.extend(a.iter().chain(&b).copied());
+                out.extend(invert(&a));
+                out.extend(invert(&b));
+            }
+            AlgNode::Conjugate(a, b) => {
+                let a = a.expand();
+                out.extend(&a);
+                b.expand_into(out);
+                out.extend(invert(&a));
+            }
+            AlgNode::Inverse(node) => out.extend(invert(&node.expand())),
+            AlgNode::Repeat(node, count) => {
+                let twists = node.expand();
+                for _ in 0..*count {
+                    out.extend(&twists);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the node is written with its own delimiters, so that a
+    /// suffix can be added without parentheses.
+    fn is_delimited(&self) -> bool {
+        match self {
+            AlgNode::Twist(_) => false,
+            AlgNode::Group(_) | AlgNode::Commutator(..) | AlgNode::Conjugate(..) => true,
+            AlgNode::Inverse(node) | AlgNode::Repeat(node, _) => node.is_delimited(),
+        }
+    }
+}
+
+/// Returns the inverse of a sequence of twists.
+fn invert(twists: &[Twist]) -> impl Iterator<Item = Twist> {
+    twists.iter().rev().map(|t| t.inv())
+}
+
+impl fmt::Display for Alg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.iter().format(" "))
+    }
+}
+impl fmt::Display for AlgNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlgNode::Twist(twist) => write!(f, "{twist}"),
+            AlgNode::Group(a) => write!(f, "({a})"),
+            AlgNode::Commutator(a, b) => write!(f, "[{a}, {b}]"),
+            AlgNode::Conjugate(a, b) => write!(f, "[{a}: {b}]"),
+            AlgNode::Inverse(node) | AlgNode::Repeat(node, _) => {
+                if node.is_delimited() {
+                    write!(f, "{node}")?;
+                } else {
+                    write!(f, "({node})")?;
+                }
+                match self {
+                    AlgNode::Repeat(_, count) => write!(f, "{count}"),
+                    _ => write!(f, "'"),
+                }
+            }
+        }
+    }
+}
+
+/// Error from parsing text, with the position of the token that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column number in characters, starting from 1.
+    pub column: usize,
+    /// Text that couldn't be parsed, which is empty at the end of the input.
+    pub token: String,
+    /// Description of what's wrong with the token.
+    pub message: String,
+    /// Possible corrections for the token.
+    pub suggestions: Vec<String>,
+}
+impl ParseError {
+    /// Constructs an error for `token` at byte offset `offset` in `source`.
+    pub fn new(source: &str, offset: usize, token: &str, message: impl Into<String>) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            token: token.to_owned(),
+            message: message.into(),
+            suggestions: vec![],
+        }
+    }
+
+    /// Constructs an error for `token`, which must be a substring of `source`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `token` isn't part of `source`.
+    pub fn at_token(source: &str, token: &str, message: impl Into<String>) -> Self {
+        let (source_range, token_range) = (
+            source.as_bytes().as_ptr_range(),
+            token.as_bytes().as_ptr_range(),
+        );
+        assert!(
+            source_range.start <= token_range.start && token_range.end <= source_range.end,
+            "token is not in source"
+        );
+        let offset = token.as_ptr() as usize - source.as_ptr() as usize;
+        Self::new(source, offset, token, message)
+    }
+
+    #[must_use]
+    pub fn with_suggestions(mut self, suggestions: Vec<String>) -> Self {
+        self.suggestions = suggestions;
+        self
+    }
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )?;
+        if !self.suggestions.is_empty() {
+            let suggestions = self.suggestions.iter().map(|s| format!("`{s}`"));
+            write!(f, " (did you mean {}?)", suggestions.format(" or "))?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for ParseError {}
+
+impl FromStr for Alg {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { s, pos: 0 };
+        let alg = parser.parse_alg()?;
+        match parser.peek() {
+            None => Ok(alg),
+            Some(c) => Err(parser.error_at_next(format!("unexpected `{c}`"))),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+    /// Skips whitespace and returns the next character.
+    fn peek(&mut self) -> Option<char> {
+        self.pos = self.s.len() - self.rest().trim_start().len();
+        self.rest().chars().next()
+    }
+    /// Returns an error for the next character, or for the end of the input.
+    fn error_at_next(&mut self, message: String) -> ParseError {
+        let token = self.peek().map_or("", |c| &self.rest()[..c.len_utf8()]);
+        ParseError::new(self.s, self.pos, token, message)
+    }
+    fn expect(&mut self, expected: &str) -> Result<char, ParseError> {
+        match self.peek() {
+            Some(c) if expected.contains(c) => {
+                self.pos += c.len_utf8();
+                Ok(c)
+            }
+            next => {
+                let expected = expected.chars().map(|c| format!("`{c}`")).join(" or ");
+                let got = next.map_or("end of input".to_owned(), |c| format!("`{c}`"));
+                Err(self.error_at_next(format!("expected {expected}, got {got}")))
+            }
+        }
+    }
+
+    /// Parses nodes until a closing delimiter or the end of the input.
+    fn parse_alg(&mut self) -> Result<Alg, ParseError> {
+        let mut nodes = vec![];
+        let mut len: usize = 0;
+        while self.peek().is_some_and(|c| !")],:".contains(c)) {
+            let start = self.pos;
+            let node = self.parse_node()?;
+            len = len.saturating_add(node.expanded_len());
+            if len > Alg::MAX_EXPANDED_LEN {
+                let token = &self.s[start..self.pos];
+                let max = Alg::MAX_EXPANDED_LEN;
+                return Err(ParseError::at_token(
+                    self.s,
+                    token,
+                    format!("algorithm expands to more than {max} twists"),
+                ));
+            }
+            nodes.push(node);
+        }
+        Ok(Alg(nodes))
+    }
+
+    fn parse_node(&mut self) -> Result<AlgNode, ParseError> {
+        let mut node = match self.peek() {
+            Some('(') => {
+                self.expect("(")?;
+                let a = self.parse_alg()?;
+                self.expect(")")?;
+                AlgNode::Group(a)
+            }
+            Some('[') => {
+                self.expect("[")?;
+                let a = self.parse_alg()?;
+                let separator = self.expect(",:")?;
+                let b = self.parse_alg()?;
+                self.expect("]")?;
+                match separator {
+                    ',' => AlgNode::Commutator(a, b),
+                    _ => AlgNode::Conjugate(a, b),
+                }
+            }
+            _ => return self.parse_twist().map(AlgNode::Twist),
+        };
+
+        // Suffixes must come right after the closing delimiter.
+        loop {
+            let rest = self.rest();
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if rest.starts_with('\'') {
+                self.pos += 1;
+                node = AlgNode::Inverse(Box::new(node));
+            } else if digits > 0 {
+                let count = &rest[..digits];
+                let count = count.parse().map_err(|_| {
+                    ParseError::at_token(self.s, count, format!("invalid repeat count `{count}`"))
+                })?;
+                self.pos += digits;
+                node = AlgNode::Repeat(Box::new(node), count);
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    fn parse_twist(&mut self) -> Result<Twist, ParseError> {
+        // Layer prefixes such as `{1,3}` may contain special characters.
+        let prefix_len = match self.rest().starts_with('{') {
+            true => self.rest().find('}').map_or(0, |i| i + 1),
+            false => 0,
+        };
+        let len = self.rest()[prefix_len..]
+            .find(|c: char| c.is_whitespace() || SPECIAL_CHARS.contains(&c))
+            .map_or(self.rest().len(), |i| prefix_len + i);
+        let word = &self.rest()[..len];
+        self.pos += len;
+        word.parse().map_err(|e| {
+            let (prefix, name) = word.split_at(prefix_len);
+            let message = format!("invalid twist `{word}`: {e}");
+            let suggestions = match TWISTS_FROM_NAME.contains_key(name) {
+                true => vec![],
+                false => similar_twist_names(name),
+            };
+            ParseError::at_token(self.s, word, message)
+                .with_suggestions(suggestions.iter().map(|n| format!("{prefix}{n}")).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_twists;
+
+    #[test]
+    fn test_alg_notation() {
+        let expand = |s: &str| s.parse::<Alg>().unwrap().expand();
+
+        assert_eq!(expand("RU UF"), parse_twists("RU UF"));
+        assert_eq!(expand("[RU, UF]"), parse_twists("RU UF RD UB"));
+        assert_eq!(expand("[RU: UF]"), parse_twists("RU UF RD"));
+        assert_eq!(expand("(RU UF)3"), parse_twists("RU UF RU UF RU UF"));
+        assert_eq!(expand("(RU UF)'"), parse_twists("UB RD"));
+        assert_eq!(expand("[RU,UF]2'"), expand("[UF, RU]2"));
+        assert_eq!(expand("[{1-2}RU: [IF, OU2]]"), {
+            parse_twists("{1-2}RU IF OU2 IB OU2 {1-2}RD")
+        });
+        assert_eq!(expand("{1,3}RU"), parse_twists("{1,3}RU"));
+        assert_eq!(expand(""), []);
+
+        for s in [
+            "RU UF",
+            "[RU, UF]",
+            "[RU: (UF IF)3]'",
+            "([RU, UF] IF)2'",
+            "[{1-2}RU, [IF: OU2]]",
+        ] {
+            assert_eq!(s.parse::<Alg>().unwrap().to_string(), s);
+        }
+        let nested = Alg(vec![AlgNode::Repeat(
+            Box::new(AlgNode::Twist(parse_twists("RU")[0])),
+            2,
+        )]);
+        assert_eq!(nested.to_string(), "(RU)2");
+        assert_eq!(
+            nested.to_string().parse(),
+            Ok(Alg(vec![AlgNode::Repeat(
+                Box::new(AlgNode::Group(Alg(vec![AlgNode::Twist(
+                    parse_twists("RU")[0]
+                )]))),
+                2,
+            )]))
+        );
+
+        for s in ["(RU", "[RU UF]", "RU)", "[RU, UF", "RX", "[RU: UF, IF]"] {
+            assert!(s.parse::<Alg>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_alg_errors() {
+        let err = "RU UF\n[IRU, UF]".parse::<Alg>().unwrap_err();
+        assert_eq!((err.line, err.column, err.token.as_str()), (2, 2, "IRU"));
+        assert_eq!(err.suggestions, ["IUR"]);
+        assert_eq!(
+            err.to_string(),
+            "line 2, column 2: invalid twist `IRU`: unknown twist name (did you mean `IUR`?)",
+        );
+
+        let err = "RU {1-2}IRU".parse::<Alg>().unwrap_err();
+        assert_eq!((err.line, err.column), (1, 4));
+        assert_eq!(err.suggestions, ["{1-2}IUR"]);
+
+        let err = "(RU UF".parse::<Alg>().unwrap_err();
+        assert_eq!((err.line, err.column, err.token.as_str()), (1, 7, ""));
+        assert_eq!(err.message, "expected `)`, got end of input");
+
+        let err = "[RU UF]".parse::<Alg>().unwrap_err();
+        assert_eq!((err.column, err.token.as_str()), (7, "]"));
+        assert_eq!(err.message, "expected `,` or `:`, got `]`");
+
+        assert_eq!(
+            crate::try_parse_twists("[RU, UF]"),
+            Ok(parse_twists("[RU, UF]"))
+        );
+        assert!(crate::try_parse_twists("RU)").is_err());
+    }
+
+    #[test]
+    fn test_alg_expanded_len() {
+        let alg = |s: &str| s.parse::<Alg>().unwrap();
+        for s in [
+            "",
+            "RU UF",
+            "[RU, UF]2",
+            "[RU: (UF IF)3]'",
+            "[{1-2}RU, [IF: OU2]]",
+        ] {
+            assert_eq!(alg(s).expanded_len(), alg(s).expand().len(), "{s}");
+        }
+
+        let max = Alg::MAX_EXPANDED_LEN;
+        assert_eq!(alg(&format!("(RU){max}")).expanded_len(), max);
+        for s in [
+            "RU UF (RU)4000000000".to_owned(),
+            format!("RU UF (RU){max}"),
+            format!("RU UF [RU, (RU){}]", max / 2),
+            "RU UF (((RU)1000)1000)1000".to_owned(),
+        ] {
+            let err = s.parse::<Alg>().unwrap_err();
+            assert_eq!((err.line, err.column), (1, 7), "{s}");
+            assert_eq!(err.token, s[6..], "{s}");
+            assert!(err.message.contains("expands to more than"), "{err}");
+        }
+        let err = "(RU)99999999999999999999".parse::<Alg>().unwrap_err();
+        assert_eq!(err.message, "invalid repeat count `99999999999999999999`");
+    }
+
+    #[test]
+    #[should_panic = "token is not in source"]
+    fn test_parse_error_token_outside_source() {
+        let source = "RU UF".to_owned();
+        let other = "RU UF".to_owned();
+        ParseError::at_token(&source, &other[3..], "");
+    }
+}
@@ -0,0 +1,154 @@
+use crate::sim::*;
+
+/// State of a finite-state automaton over grips that accepts exactly one
+/// ordering of each twist sequence, up to twists that commute.
+///
+/// Twists on opposite grips commute, and consecutive twists on the same grip
+/// can be combined into one, so the automaton accepts a sequence iff each
+/// maximal run of twists on the same axis is either a single twist or a twist
+/// on the positive grip followed by a twist on the negative grip.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TwistSeqState(u8);
+
+/// Number of automaton states:
+///
+/// - `0`: start
+/// - `1..=8`: last twist was on grip `state - 1`
+/// - `9..=12`: last two twists were on both grips of axis `state - 9`
+const STATE_COUNT: usize = 13;
+
+/// Entry in [`TRANSITIONS`] for grips that may not be twisted.
+const REJECT: u8 = u8::MAX;
+
+/// Next state, indexed by state and then by grip ID.
+const TRANSITIONS: [[u8; 8]; STATE_COUNT] = {
+    let mut ret = [[REJECT; 8]; STATE_COUNT];
+    let mut state = 0;
+    while state < STATE_COUNT {
+        let mut grip = 0;
+        while grip < 8 {
+            let axis = grip >> 1;
+            ret[state][grip] = match state {
+                0 => grip as u8 + 1,
+                1..=8 => {
+                    let last_grip = state - 1;
+                    if last_grip == grip {
+                        REJECT // same grip as last move
+                    } else if last_grip >> 1 != axis {
+                        grip as u8 + 1
+                    } else if last_grip < grip {
+                        9 + axis as u8
+                    } else {
+                        REJECT // opposite grips must be in ascending order
+                    }
+                }
+                _ if state - 9 == axis => REJECT, // both grips already moved
+                _ => grip as u8 + 1,
+            };
+            grip += 1;
+        }
+        state += 1;
+    }
+    ret
+};
+
+/// Grips that may be twisted, indexed by state.
+const ALLOWED_GRIPS: [GripSet; STATE_COUNT] = {
+    let mut ret = [GripSet::NONE; STATE_COUNT];
+    let mut state = 0;
+    while state < STATE_COUNT {
+        let mut grip = 0;
+        while grip < 8 {
+            if TRANSITIONS[state][grip] != REJECT {
+                ret[state].0 |= 1 << grip;
+            }
+            grip += 1;
+        }
+        state += 1;
+    }
+    ret
+};
+
+impl TwistSeqState {
+    /// State for an empty twist sequence.
+    pub const START: Self = Self(0);
+
+    /// Returns the state after twisting `grip`, or `None` if the resulting
+    /// sequence is not canonical.
+    #[inline]
+    pub fn next(self, grip: GripId) -> Option<Self> {
+        let next = TRANSITIONS[self.0 as usize][grip.id() as usize];
+        (next != REJECT).then_some(Self(next))
+    }
+
+    /// Returns the set of grips that may be twisted next.
+    #[inline]
+    pub fn allowed_grips(self) -> GripSet {
+        ALLOWED_GRIPS[self.0 as usize]
+    }
+
+    /// Returns the state after a sequence of twists on `grips`, or `None` if
+    /// the sequence is not canonical.
+    pub fn after(grips: impl IntoIterator<Item = GripId>) -> Option<Self> {
+        grips.into_iter().try_fold(Self::START, Self::next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    /// Returns whether `grips` is canonical, by checking each maximal run of
+    /// grips on the same axis.
+    fn is_canonical(grips: &[GripId]) -> bool {
+        grips
+            .chunk_by(|a, b| a.axis() == b.axis())
+            .all(|run| match run {
+                [_] => true,
+                [a, b] => a < b,
+                _ => false,
+            })
+    }
+
+    /// Returns whether `grips` is accepted by the rules that `dfs_blockbuild`
+    /// used before the automaton.
+    fn is_accepted_by_old_rules(grips: &[GripId]) -> bool {
+        grips.windows(2).all(|w| w[0] != w[1])
+            && grips
+                .windows(3)
+                .all(|w| !(w[0] == w[2] && w[1] == w[2].opposite()))
+    }
+
+    #[test]
+    fn test_twist_seq_automaton() {
+        // Number of grip sequences of each length accepted by the automaton
+        // and by the old rules.
+        let expected_counts = [
+            (1, 1),
+            (8, 8),
+            (52, 56),
+            (336, 384),
+            (2172, 2640),
+            (14040, 18144),
+        ];
+        for (len, expected) in expected_counts.into_iter().enumerate() {
+            let mut old_count = 0;
+            let mut new_count = 0;
+            for grips in itertools::repeat_n(HYPERCUBE_GRIPS, len).multi_cartesian_product() {
+                let state = TwistSeqState::after(grips.iter().copied());
+                assert_eq!(is_canonical(&grips), state.is_some(), "{grips:?}");
+                if let Some(state) = state {
+                    for g in HYPERCUBE_GRIPS {
+                        assert_eq!(state.allowed_grips().contains(g), state.next(g).is_some());
+                    }
+                }
+
+                old_count += is_accepted_by_old_rules(&grips) as usize;
+                new_count += state.is_some() as usize;
+            }
+            assert_eq!((new_count, old_count), expected, "length {len}");
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    BlockBuildingSearchParams, CanonicalKey, Segment, SolutionMetadata, TwistSeqState, canonicalize,
+};
+use crate::StackVec;
+use crate::sim::*;
+
+type TwistSeq = StackVec<Twist, { crate::MAX_SOLUTION_SEGMENT_LEN }>;
+
+/// Sorted piece counts of the blocks in a state. This doesn't change when the
+/// state is rotated and is much cheaper to compute than a [`CanonicalKey`].
+type Signature = StackVec<u8, { crate::MAX_BLOCKS }>;
+
+fn signature(state: BlockSet) -> Signature {
+    state
+        .blocks
+        .map(|b| b.layers().piece_count() as u8)
+        .sorted_unstable()
+}
+
+/// Table of every state that can be solved to a single goal block within a
+/// fixed number of twists, along with a solution for each one.
+///
+/// States are stored up to the rotations that fix the goal block, so that each
+/// entry covers several states.
+pub struct BackwardTable {
+    goal: Block,
+    depth: usize,
+    /// Rotations that fix the goal block.
+    symmetries: Vec<ElemId>,
+    /// Signatures of all states in the table, which are checked before
+    /// computing the more expensive [`CanonicalKey`].
+    signatures: HashSet<Signature>,
+    /// Solution for each canonical state.
+    solutions: HashMap<CanonicalKey, TwistSeq>,
+}
+impl BackwardTable {
+    /// Builds a table using breadth-first search backward from `goal` to
+    /// `depth`.
+    ///
+    /// `goal` must have the identity attitude.
+    pub fn new(puzzle: &Puzzle, goal: Block, depth: usize) -> Self {
+        assert_eq!(goal.attitude(), IDENT, "goal block must be solved");
+
+        let symmetries = puzzle
+            .rotations
+            .iter()
+            .copied()
+            .filter(|&rot| rot * goal.layers() == goal.layers())
+            .collect();
+        let mut ret = Self {
+            goal,
+            depth,
+            symmetries,
+            signatures: HashSet::new(),
+            solutions: HashMap::new(),
+        };
+
+        let goal_state = BlockSet {
+            blocks: StackVec::from_iter([goal]).unwrap(),
+        };
+        ret.insert(puzzle, goal_state, TwistSeq::new());
+
+        // Each twist in the backward search is prepended to the solution as
+        // its inverse.
+        let mut frontier = vec![(goal_state, TwistSeq::new(), TwistSeqState::START)];
+        for _ in 0..depth {
+            let mut next_frontier = vec![];
+            for (state, solution, seq_state) in frontier {
+                let grips_worth_testing = if !puzzle.has_only_outer_twists() {
+                    puzzle.grip_set()
+                } else {
+                    seq_state.allowed_grips() & super::moved_grips(state)
+                };
+                let grips =
+                    (puzzle.grips.iter()).filter(|grip| grips_worth_testing.contains(grip.id));
+                let last_twist = solution.first().copied();
+                for twist in grips.flat_map(|grip| grip.twists()) {
+                    if !super::is_twist_worth_testing(puzzle, state, last_twist, twist) {
+                        continue;
+                    }
+                    let Some(new_state) = state.do_twist(twist, puzzle) else {
+                        continue;
+                    };
+                    let new_seq_state = match puzzle.has_only_outer_twists() {
+                        false => seq_state,
+                        true => match seq_state.next(twist.grip()) {
+                            Some(new_seq_state) => new_seq_state,
+                            None => continue,
+                        },
+                    };
+                    let Some(new_solution) =
+                        TwistSeq::from_iter(std::iter::once(twist.inv()).chain(solution))
+                    else {
+                        continue;
+                    };
+                    if ret.insert(puzzle, new_state, new_solution) {
+                        next_frontier.push((new_state, new_solution, new_seq_state));
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+
+        ret
+    }
+
+    /// Adds `state` to the table if it isn't already there, and returns whether
+    /// it was added.
+    fn insert(&mut self, puzzle: &Puzzle, state: BlockSet, solution: TwistSeq) -> bool {
+        let (key, rot) = self.canonicalize(puzzle, state);
+        match self.solutions.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(e) => {
+                e.insert(solution.map(|twist| rot.transform(twist)));
+                self.signatures.insert(signature(state));
+                true
+            }
+        }
+    }
+
+    fn canonicalize(&self, puzzle: &Puzzle, state: BlockSet) -> (CanonicalKey, ElemId) {
+        canonicalize(
+            state,
+            SolutionMetadata::default(),
+            &self.symmetries,
+            puzzle.ndim,
+        )
+    }
+
+    /// Returns the goal block.
+    pub fn goal(&self) -> Block {
+        self.goal
+    }
+    /// Returns the maximum length of a solution in the table.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    /// Returns the number of canonical states in the table.
+    pub fn len(&self) -> usize {
+        self.solutions.len()
+    }
+    /// Returns whether the table is empty, which is never true.
+    pub fn is_empty(&self) -> bool {
+        self.solutions.is_empty()
+    }
+
+    /// Returns a rotation that takes `goal` to the goal block of the table, or
+    /// `None` if there is none.
+    pub fn rotation_from(&self, puzzle: &Puzzle, goal: Block) -> Option<ElemId> {
+        let goal_layers = goal.at_solved().layers();
+        (puzzle.rotations.iter().copied()).find(|&rot| rot * goal_layers == self.goal.layers())
+    }
+
+    /// Returns a twist sequence from the table that takes `state` to the goal
+    /// block, or `None` if `state` isn't in the table.
+    ///
+    /// The way that blocks get merged depends on their order, so it's possible
+    /// (though rare) for the solution not to work.
+    pub fn solution(&self, puzzle: &Puzzle, state: BlockSet) -> Option<TwistSeq> {
+        if !self.signatures.contains(&signature(state)) {
+            return None;
+        }
+        let (key, rot) = self.canonicalize(puzzle, state);
+        let inv = rot.inv();
+        Some(self.solutions.get(&key)?.map(|twist| inv.transform(twist)))
+    }
+}
+
+/// Runs a depth-first search to `remaining_depth` for sequences of moves that
+/// result in a state in `table`, and completes each one using the solution from
+/// the table.
+///
+/// `to_table_frame` is a rotation that takes the goal block for
+/// `solution_so_far` to the goal block of `table`. See
+/// [`BackwardTable::rotation_from()`].
+///
+/// Results are accumulated into `solutions_buffer`.
+#[allow(clippy::too_many_arguments)]
+pub fn dfs_bidirectional(
+    params: BlockBuildingSearchParams,
+    puzzle: &Puzzle,
+    table: &BackwardTable,
+    to_table_frame: ElemId,
+    remaining_depth: usize,
+    solutions_buffer: &mut Vec<Segment>,
+    solution_so_far: Segment,
+) {
+    let Segment {
+        state,
+        segment_twists,
+        ..
+    } = solution_so_far;
+
+    let last_twist = segment_twists.last().copied();
+
+    if let Some(solution) = table.solution(puzzle, to_table_frame.transform(state)) {
+        let from_table_frame = to_table_frame.inv();
+        let mut last_twist = last_twist;
+        let mut segment = Some(solution_so_far.clone());
+        for twist in solution.map(|twist| from_table_frame.transform(twist)) {
+            segment = segment.and_then(|s| s.push_twist(puzzle, params.metric, twist, last_twist));
+            last_twist = Some(twist);
+        }
+        if let Some(segment) = segment
+            && segment.state.is_solved()
+        {
+            solutions_buffer.push(segment);
+            return;
+        }
+    }
+    if remaining_depth == 0 {
+        return; // no more to search; give up
+    }
+
+    let total_remaining_depth = remaining_depth + table.depth();
+
+    if !params
+        .heuristic
+        .might_be_solvable(puzzle, state, 1, total_remaining_depth)
+    {
+        return; // probably not solvable; give up
+    }
+
+    if let Some(db) = params.pattern_database
+        && !puzzle.slice_twists
+        && db.min_block_count(state, total_remaining_depth) > 1
+    {
+        return; // definitely not solvable; give up
+    }
+
+    let grips_worth_testing = super::grips_worth_testing(puzzle, state, &segment_twists);
+    let grips = (puzzle.grips.iter()).filter(|grip| grips_worth_testing.contains(grip.id));
+    let twists = (grips.flat_map(|grip| grip.twists()))
+        .filter(|&twist| super::is_twist_worth_testing(puzzle, state, last_twist, twist));
+    for twist in twists {
+        if let Some(new_partial_solution) =
+            solution_so_far.push_twist(puzzle, params.metric, twist, last_twist)
+        {
+            dfs_bidirectional(
+                params,
+                puzzle,
+                table,
+                to_table_frame,
+                remaining_depth - 1,
+                solutions_buffer,
+                new_partial_solution,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn f2l_block() -> Block {
+        Block::new_solved([], [O]).unwrap()
+    }
+
+    #[test]
+    fn test_backward_table() {
+        let table = BackwardTable::new(&RUBIKS_4D, f2l_block(), 2);
+        let goal_state = BlockSet {
+            blocks: StackVec::from_iter([f2l_block()]).unwrap(),
+        };
+
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for _ in 0..100 {
+            let scramble = RUBIKS_4D.random_moves(&mut rng, 2);
+            let mut state = goal_state;
+            for &twist in &scramble {
+                state = state.do_twist(twist, &RUBIKS_4D).unwrap();
+            }
+
+            let solution = table.solution(&RUBIKS_4D, state).unwrap();
+            assert!(solution.len() <= 2);
+            for &twist in &solution {
+                state = state.do_twist(twist, &RUBIKS_4D).unwrap();
+            }
+            assert!(state.is_solved(), "{}", state);
+        }
+    }
+
+    #[test]
+    fn test_bidirectional_search() {
+        let table = BackwardTable::new(&RUBIKS_4D, f2l_block(), 2);
+        let goal = Block::new_solved([], [R]).unwrap();
+        let to_table_frame = table.rotation_from(&RUBIKS_4D, goal).unwrap();
+
+        // The scramble is longer than the table, but short enough for
+        // bidirectional search to undo.
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let scramble = RUBIKS_4D.random_moves(&mut rng, 3);
+        let mut state = BlockSet {
+            blocks: StackVec::from_iter([goal]).unwrap(),
+        };
+        for &twist in &scramble {
+            state = state.do_twist(twist, &RUBIKS_4D).unwrap();
+        }
+        let init = Segment {
+            state,
+            ..Default::default()
+        };
+
+        let mut solutions = vec![];
+        let params = BlockBuildingSearchParams::default();
+        dfs_bidirectional(
+            params,
+            &RUBIKS_4D,
+            &table,
+            to_table_frame,
+            1,
+            &mut solutions,
+            init,
+        );
+        assert!(!solutions.is_empty());
+        for solution in solutions {
+            assert!(solution.segment_twists.len() <= 3);
+            let mut state = state;
+            for &twist in &solution.segment_twists {
+                state = state.do_twist(twist, &RUBIKS_4D).unwrap();
+            }
+            assert!(state.is_solved());
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::io;
+use std::path::Path;
+
+use super::{SegmentStore, Solver};
+use crate::sim::*;
+use crate::{Profile, StackVec};
+
+const MAGIC_STRING: &[u8] = b"robodoan-checkpoint-v4\n";
+
+/// Binary encoding used for checkpoints.
+pub(super) trait Encode: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    /// Decodes a value from the start of `input` and advances `input` past it,
+    /// or returns `None` if `input` is invalid.
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        Some(byte)
+    }
+}
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        u8::from(*self).encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+impl Encode for u16 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.to_le_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let (bytes, rest) = input.split_first_chunk()?;
+        *input = rest;
+        Some(Self::from_le_bytes(*bytes))
+    }
+}
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend((*self as u64).to_le_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let (bytes, rest) = input.split_first_chunk()?;
+        *input = rest;
+        u64::from_le_bytes(*bytes).try_into().ok()
+    }
+}
+
+impl Encode for ElemId {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id().encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Self::from_id(u8::decode(input)?)
+    }
+}
+impl Encode for GripId {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id().encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Self::try_new(u8::decode(input)?)
+    }
+}
+impl Encode for Option<GripId> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.map_or(u8::MAX, |g| g.id()).encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            u8::MAX => Some(None),
+            id => Some(Some(GripId::try_new(id)?)),
+        }
+    }
+}
+impl Encode for Twist {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.grip().encode(out);
+        self.transform.encode(out);
+        self.layers().mask().encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let twist = Self::new(GripId::decode(input)?, ElemId::decode(input)?);
+        Some(twist.with_layers(TwistLayers::new(u8::decode(input)?)?))
+    }
+}
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.layers().to_u16().encode(out);
+        self.attitude().encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let layers = PackedLayers::from_u16(u16::decode(input)?);
+        Block::from_parts(layers, ElemId::decode(input)?)
+    }
+}
+impl Encode for BlockSet {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.blocks.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            blocks: StackVec::decode(input)?,
+        })
+    }
+}
+impl Encode for PuzzleState {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.piece_attitudes()
+            .iter()
+            .for_each(|elem| elem.encode(out));
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let piece_attitudes: Option<Vec<ElemId>> = (0..72).map(|_| ElemId::decode(input)).collect();
+        Some(Self::from_piece_attitudes(
+            piece_attitudes?.try_into().ok()?,
+        ))
+    }
+}
+impl<T: Encode + Default + Copy, const CAP: usize> Encode for StackVec<T, CAP> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        u8::try_from(self.len()).unwrap().encode(out);
+        self.iter().for_each(|elem| elem.encode(out));
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = u8::decode(input)?;
+        (0..len)
+            .map(|_| T::decode(input))
+            .try_fold(Self::new(), |v, elem| v.push(elem?))
+    }
+}
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        self.iter().for_each(|elem| elem.encode(out));
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = usize::decode(input)?;
+        // Don't trust `len` for preallocation.
+        (0..len).map(|_| T::decode(input)).collect()
+    }
+}
+
+impl Encode for Profile {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let byte: u8 = match self {
+            Profile::Fast => 0,
+            Profile::Short => 1,
+        };
+        byte.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(Profile::Fast),
+            1 => Some(Profile::Short),
+            _ => None,
+        }
+    }
+}
+
+impl Solver {
+    /// Saves the scramble or initial state, the segments found so far, and the
+    /// number of completed stages to `path`, overwriting it if it exists.
+    pub fn save_checkpoint(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut bytes = MAGIC_STRING.to_vec();
+        self.encode_settings(&mut bytes);
+        self.stage.encode(&mut bytes);
+        self.segments.encode(&mut bytes);
+
+        // Write to a temporary file first so that an interrupted write doesn't
+        // destroy the previous checkpoint.
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bytes)?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    /// Loads the segments and completed stages from a checkpoint saved by
+    /// [`Solver::save_checkpoint()`]. [`Solver::solve()`] continues from the
+    /// stage after the last one that was completed.
+    ///
+    /// Segment lengths depend on the profile, metric, and puzzle, so the
+    /// checkpoint must have been saved by a solver with the same ones, and
+    /// with the same scramble or initial state. Other search parameters may be
+    /// changed before resuming.
+    pub fn resume(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid checkpoint file");
+
+        let mut input = bytes.strip_prefix(MAGIC_STRING).ok_or_else(invalid)?;
+        let mut settings = vec![];
+        self.encode_settings(&mut settings);
+        input = input.strip_prefix(settings.as_slice()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checkpoint was saved with a different profile, metric, or puzzle",
+            )
+        })?;
+        let stage = usize::decode(&mut input).ok_or_else(invalid)?;
+        let segments = SegmentStore::decode(&mut input).ok_or_else(invalid)?;
+        if !input.is_empty() || stage > Solver::STAGE_COUNT {
+            return Err(invalid());
+        }
+        if segments.scramble != self.segments.scramble
+            || segments.scrambled_state() != self.scrambled_state()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checkpoint is for a different scramble",
+            ));
+        }
+
+        self.stage = stage;
+        self.segments = segments;
+        Ok(self)
+    }
+
+    /// Encodes the settings that segment lengths depend on.
+    fn encode_settings(&self, out: &mut Vec<u8>) {
+        self.profile.encode(out);
+        self.puzzle.slice_twists.encode(out);
+        self.puzzle.rotation_twists.encode(out);
+        self.params.metric.encode(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::search::{CostModel, Metric, Segment, SolutionMetadata};
+
+    /// Returns a checkpoint path that no other test or test run uses.
+    fn checkpoint_path(test_name: &str) -> std::path::PathBuf {
+        let file_name = format!("robodoan-{test_name}-{}.bin", std::process::id());
+        std::env::temp_dir().join(file_name)
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let scramble = RUBIKS_4D.random_moves(&mut rng, 20);
+        let mut solver = Solver::new(Profile::Short, scramble.clone());
+
+        // Add some segments by hand instead of running a whole stage.
+        let init = solver.segments[Default::default()].clone();
+        let new_segments = SolutionMetadata::default()
+            .stage1()
+            .into_iter()
+            .filter_map(|(block, meta)| {
+                init.push_block(&RUBIKS_4D, &PuzzleState::default(), &scramble, block, meta)
+            })
+            .map(|segment: Segment| {
+                segment
+                    .push_twist(&RUBIKS_4D, Metric::Stm, RUBIKS_4D.twists[5], None)
+                    .unwrap()
+            })
+            .collect();
+        solver.segments.add_segments(1, new_segments);
+        solver.stage = 1;
+
+        let path = checkpoint_path("test-checkpoint");
+        solver.save_checkpoint(&path).unwrap();
+        let resumed = Solver::new(Profile::Short, scramble.clone()).resume(&path);
+        std::fs::remove_file(&path).unwrap();
+        let resumed = resumed.unwrap();
+
+        assert_eq!(solver.profile, resumed.profile);
+        assert_eq!(solver.stage, resumed.stage);
+        assert_eq!(scramble, resumed.segments.scramble);
+        assert_eq!(solver.scrambled_state(), resumed.scrambled_state());
+        let ids = solver.segments.best_solutions_so_far().unwrap();
+        assert_eq!(ids, resumed.segments.best_solutions_so_far().unwrap());
+        for &id in ids {
+            assert_eq!(solver.segments[id], resumed.segments[id]);
+        }
+
+        std::fs::write(&path, b"robodoan-checkpoint-v4\nnonsense").unwrap();
+        assert!(Solver::new(Profile::Short, scramble).resume(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_settings_mismatch() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(1);
+        let scramble = RUBIKS_4D.random_moves(&mut rng, 20);
+        let cost_model = CostModel::default().with_rotation_cost(5);
+        let new_solver = || {
+            Solver::new(Profile::Short, scramble.clone())
+                .with_metric(Metric::Cost(cost_model.clone().intern().unwrap()))
+        };
+
+        let path = checkpoint_path("test-checkpoint-settings-mismatch");
+        new_solver().save_checkpoint(&path).unwrap();
+        let other_scramble = RUBIKS_4D.random_moves(&mut rng, 20);
+        let other_cost_model = cost_model.clone().with_rotation_cost(6);
+        let results = [
+            new_solver().resume(&path).map(|_| ()),
+            new_solver()
+                .with_metric(Metric::Rtm)
+                .resume(&path)
+                .map(|_| ()),
+            new_solver()
+                .with_metric(Metric::Cost(other_cost_model.intern().unwrap()))
+                .resume(&path)
+                .map(|_| ()),
+            new_solver()
+                .with_slice_twists(true)
+                .resume(&path)
+                .map(|_| ()),
+            new_solver().with_rotations(true).resume(&path).map(|_| ()),
+            Solver::new(Profile::Fast, scramble.clone())
+                .with_metric(Metric::Cost(cost_model.clone().intern().unwrap()))
+                .resume(&path)
+                .map(|_| ()),
+            Solver::new(Profile::Short, other_scramble)
+                .with_metric(Metric::Cost(cost_model.clone().intern().unwrap()))
+                .resume(&path)
+                .map(|_| ()),
+        ];
+        std::fs::remove_file(&path).unwrap();
+
+        let [same, different @ ..] = results;
+        same.unwrap();
+        for result in different {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_from_state() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(2);
+        let scramble = RUBIKS_4D.random_moves(&mut rng, 20);
+        let state = PuzzleState::from_twists(&scramble);
+        let solver = Solver::from_state(Profile::Short, state.clone());
+        assert!(solver.scramble().is_empty());
+        assert_eq!(state, solver.scrambled_state());
+
+        // The first pieces are where they would be after the scramble.
+        let init = solver.segments[Default::default()].clone();
+        for (block, meta) in SolutionMetadata::default().stage1() {
+            let from_scramble =
+                init.push_block(&RUBIKS_4D, &PuzzleState::default(), &scramble, block, meta);
+            let from_state = init.push_block(&RUBIKS_4D, &state, &[], block, meta);
+            // Centers start out solved instead of being twisted by the
+            // scramble, so they may merge with different pieces.
+            assert_eq!(
+                from_scramble.map(|s| s.state.combined_layers()),
+                from_state.map(|s| s.state.combined_layers()),
+            );
+        }
+
+        let path = checkpoint_path("test-checkpoint-from-state");
+        solver.save_checkpoint(&path).unwrap();
+        let resumed = Solver::from_state(Profile::Short, state.clone()).resume(&path);
+        let from_scramble = Solver::new(Profile::Short, scramble).resume(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state, resumed.unwrap().scrambled_state());
+        assert!(from_scramble.is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::checkpoint::Encode;
+use super::{
+    BlockBuildingSearchParams, CostModel, Heuristic, Metric, PATTERN_DATABASE_4D, Segment,
+    SegmentId, Solver, blockbuild_from, search_each,
+};
+use crate::sim::*;
+
+/// Sent by both ends of a connection before anything else, so that connecting
+/// to the wrong process or to an incompatible version fails immediately.
+const MAGIC_STRING: &[u8] = b"robodoan-worker-v3\n";
+
+/// Maximum number of coordinators that a worker serves at once. Each one gets
+/// its own thread.
+const MAX_CONNECTIONS: usize = 8;
+
+/// Time that a worker waits for a new connection to send [`MAGIC_STRING`],
+/// so that idle connections don't take up one of [`MAX_CONNECTIONS`].
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Encode for Heuristic {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let byte: u8 = match self {
+            Heuristic::Fast => 0,
+            Heuristic::Correct => 1,
+            Heuristic::None => 2,
+        };
+        byte.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(Heuristic::Fast),
+            1 => Some(Heuristic::Correct),
+            2 => Some(Heuristic::None),
+            _ => None,
+        }
+    }
+}
+impl Encode for CostModel {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for &twist in &RUBIKS_4D.twists {
+            u16::try_from(self.twist_cost(twist)).unwrap().encode(out);
+        }
+        self.rotation_cost().encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let ret = RUBIKS_4D
+            .twists
+            .iter()
+            .try_fold(Self::default(), |ret, &twist| {
+                Some(ret.with_twist_cost(twist, u16::decode(input)?))
+            })?;
+        Some(ret.with_rotation_cost(u16::decode(input)?))
+    }
+}
+impl Encode for Metric {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let byte: u8 = match self {
+            Metric::Etm => 0,
+            Metric::Stm => 1,
+            Metric::Qtm => 2,
+            Metric::Rtm => 3,
+            Metric::Cost(_) => 4,
+        };
+        byte.encode(out);
+        if let Metric::Cost(cost_model) = self {
+            cost_model.encode(out);
+        }
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(Metric::Etm),
+            1 => Some(Metric::Stm),
+            2 => Some(Metric::Qtm),
+            3 => Some(Metric::Rtm),
+            // Workers see the same few cost models over and over, so interning
+            // them doesn't leak much. Past the limit, the request is rejected.
+            4 => Some(Metric::Cost(CostModel::decode(input)?.intern().ok()?)),
+            _ => None,
+        }
+    }
+}
+impl Encode for BlockBuildingSearchParams {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.heuristic.encode(out);
+        self.metric.encode(out);
+        self.max_depth.encode(out);
+        // Each process chooses its own parallel depth if it isn't set.
+        self.parallel_depth.map_or(0, |depth| depth + 1).encode(out);
+        // Every 4D pattern database is the same as `PATTERN_DATABASE_4D`,
+        // which each process builds for itself.
+        self.pattern_database.is_some().encode(out);
+        self.bidirectional_depth.encode(out);
+        self.deterministic.encode(out);
+        self.verbosity.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            heuristic: Heuristic::decode(input)?,
+            metric: Metric::decode(input)?,
+            max_depth: usize::decode(input)?,
+            parallel_depth: usize::decode(input)?.checked_sub(1),
+            pattern_database: bool::decode(input)?.then(|| &*PATTERN_DATABASE_4D),
+            bidirectional_depth: usize::decode(input)?,
+            deterministic: bool::decode(input)?,
+            verbosity: u8::decode(input)?,
+        })
+    }
+}
+
+/// Request for a worker to run [`blockbuild_from()`] from each segment in a
+/// shard.
+struct ShardRequest {
+    params: BlockBuildingSearchParams,
+    /// One of the puzzles from [`rubiks_4d()`].
+    puzzle: &'static Puzzle,
+    block_target: usize,
+    depth: usize,
+    /// Maximum number of solutions to return.
+    limit: usize,
+    segments: Vec<Segment>,
+}
+impl Encode for ShardRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.params.encode(out);
+        self.puzzle.slice_twists.encode(out);
+        self.puzzle.rotation_twists.encode(out);
+        self.block_target.encode(out);
+        self.depth.encode(out);
+        self.limit.encode(out);
+        self.segments.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            params: BlockBuildingSearchParams::decode(input)?,
+            puzzle: rubiks_4d(bool::decode(input)?, bool::decode(input)?),
+            block_target: usize::decode(input)?,
+            depth: usize::decode(input)?,
+            limit: usize::decode(input)?,
+            segments: Vec::decode(input)?,
+        })
+    }
+}
+impl ShardRequest {
+    fn search(&self) -> Vec<Segment> {
+        search_each(
+            self.params.deterministic,
+            &self.segments,
+            self.limit,
+            |segment| {
+                blockbuild_from(
+                    self.params,
+                    self.puzzle,
+                    self.block_target,
+                    self.depth,
+                    segment.clone(),
+                )
+            },
+        )
+    }
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid message from peer")
+}
+
+/// Writes a length-prefixed message to `stream`.
+fn write_message(stream: &mut TcpStream, message: &impl Encode) -> io::Result<()> {
+    let mut payload = vec![];
+    message.encode(&mut payload);
+    let mut bytes = vec![];
+    payload.len().encode(&mut bytes);
+    bytes.extend(payload);
+    stream.write_all(&bytes)
+}
+
+/// Reads a length-prefixed message from `stream`.
+fn read_message<T: Encode>(stream: &mut TcpStream) -> io::Result<T> {
+    let mut len_bytes = [0; 8];
+    stream.read_exact(&mut len_bytes)?;
+    let len = usize::decode(&mut &len_bytes[..]).ok_or_else(invalid_data)?;
+
+    // Don't trust `len` for preallocation.
+    let mut payload = vec![];
+    stream.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let mut input = payload.as_slice();
+    let message = T::decode(&mut input).ok_or_else(invalid_data)?;
+    if !input.is_empty() {
+        return Err(invalid_data());
+    }
+    Ok(message)
+}
+
+/// Sends [`MAGIC_STRING`] and checks that the peer sends it too.
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    stream.write_all(MAGIC_STRING)?;
+    let mut magic = [0; MAGIC_STRING.len()];
+    stream.read_exact(&mut magic)?;
+    if magic != MAGIC_STRING {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer is not a compatible robodoan process",
+        ));
+    }
+    Ok(())
+}
+
+/// Serves shard requests from coordinators that connect to `listener`, each on
+/// its own thread. Each shard is searched in parallel using rayon.
+///
+/// Connections beyond [`MAX_CONNECTIONS`] are closed immediately. Coordinators
+/// are not authenticated, so `listener` should only be reachable from trusted
+/// machines.
+///
+/// This only returns if `listener` fails.
+pub fn run_worker(listener: TcpListener) -> io::Result<()> {
+    let connection_count = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr();
+        // Only this thread increments the count, so it can't go over the
+        // limit between checking and incrementing it.
+        if connection_count.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            println!("WARNING: refused connection from {peer:?}: too many connections");
+            continue;
+        }
+        connection_count.fetch_add(1, Ordering::SeqCst);
+        let connection_count = Arc::clone(&connection_count);
+        std::thread::spawn(move || {
+            if let Err(e) = serve_coordinator(stream) {
+                println!("WARNING: connection to {peer:?} failed: {e}");
+            }
+            connection_count.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+fn serve_coordinator(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    handshake(&mut stream)?;
+    // Searches can take arbitrarily long, so the coordinator may take
+    // arbitrarily long to send the next request.
+    stream.set_read_timeout(None)?;
+    loop {
+        let request: ShardRequest = match read_message(&mut stream) {
+            Ok(request) => request,
+            // The coordinator closed the connection.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        write_message(&mut stream, &request.search())?;
+    }
+}
+
+/// Connection to a worker process started using [`run_worker()`].
+pub(super) struct Worker {
+    addr: SocketAddr,
+    /// Connection to the worker, or `None` if it has failed.
+    stream: Mutex<Option<TcpStream>>,
+}
+impl Worker {
+    fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        handshake(&mut stream)?;
+        Ok(Self {
+            addr: stream.peer_addr()?,
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+
+    /// Sends `request` to the worker and waits for the results, or returns
+    /// `None` if the worker has failed.
+    fn search(&self, request: &ShardRequest) -> Option<Vec<Segment>> {
+        let mut stream = self.stream.lock().unwrap();
+        let connection = stream.as_mut()?;
+        let result = write_message(connection, request).and_then(|()| read_message(connection));
+        match result {
+            Ok(segments) => Some(segments),
+            Err(e) => {
+                // The connection may be in the middle of a message, so it
+                // can't be used again.
+                println!("  WARNING: worker {} failed: {e}", self.addr);
+                *stream = None;
+                None
+            }
+        }
+    }
+}
+
+impl Solver {
+    /// Connects to worker processes at `addrs`, each started using
+    /// [`run_worker()`], and splits each blockbuilding step evenly between
+    /// them and this process.
+    ///
+    /// If a worker fails, its share is searched locally for the rest of the
+    /// solve. Bidirectional search always runs locally.
+    pub fn with_workers<A: ToSocketAddrs>(
+        mut self,
+        addrs: impl IntoIterator<Item = A>,
+    ) -> io::Result<Self> {
+        for addr in addrs {
+            self.workers.push(Worker::connect(addr)?);
+        }
+        Ok(self)
+    }
+
+    /// Runs [`blockbuild_from()`] from each segment in `prev_segments`, split
+    /// between this process and the workers, and returns at most `limit` of
+    /// the results.
+    pub(super) fn blockbuild_shards(
+        &self,
+        prev_segments: &[SegmentId],
+        block_target: usize,
+        depth: usize,
+        limit: usize,
+    ) -> Vec<Segment> {
+        let search_locally = |shard: &[SegmentId]| {
+            search_each(self.params.deterministic, shard, limit, |&id| {
+                let segment = self.segments[id].next_step(id);
+                blockbuild_from(self.params, self.puzzle, block_target, depth, segment)
+            })
+        };
+
+        if self.workers.is_empty() {
+            return search_locally(prev_segments);
+        }
+
+        let shard_size = prev_segments.len().div_ceil(self.workers.len() + 1);
+        let mut shards = prev_segments.chunks(shard_size.max(1));
+        let local_shard = shards.next().unwrap_or_default();
+
+        let mut results = std::thread::scope(|s| {
+            let remote_results: Vec<_> = std::iter::zip(&self.workers, shards)
+                .map(|(worker, shard)| {
+                    s.spawn(move || {
+                        let request = ShardRequest {
+                            params: self.params,
+                            puzzle: self.puzzle,
+                            block_target,
+                            depth,
+                            limit,
+                            segments: shard
+                                .iter()
+                                .map(|&id| self.segments[id].next_step(id))
+                                .collect(),
+                        };
+                        worker
+                            .search(&request)
+                            .unwrap_or_else(|| self.install(|| search_locally(shard)))
+                    })
+                })
+                .collect();
+
+            let mut results = search_locally(local_shard);
+            for handle in remote_results {
+                results.extend(handle.join().unwrap());
+            }
+            results
+        });
+        results.truncate(limit);
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::Profile;
+    use crate::search::SolutionMetadata;
+
+    fn spawn_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || run_worker(listener));
+        addr
+    }
+
+    /// Returns a solver that has completed the first step of stage 1, along
+    /// with the segments from that step and a block target for the next one.
+    fn stage1_solver() -> (Solver, Vec<SegmentId>, usize) {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let scramble = RUBIKS_4D.random_moves(&mut rng, 20);
+        let mut solver = Solver::new(Profile::Fast, scramble.clone());
+        let init = solver.segments[Default::default()].clone();
+        let new_segments = SolutionMetadata::default()
+            .stage1()
+            .into_iter()
+            .filter_map(|(block, meta)| {
+                init.push_block(&RUBIKS_4D, &PuzzleState::default(), &scramble, block, meta)
+            })
+            .collect();
+        solver.segments.add_segments(1, new_segments);
+
+        let prev_segments = solver.segments.segment_ids_for_step(1).to_vec();
+        assert!(prev_segments.len() > 2);
+        let block_target = prev_segments
+            .iter()
+            .map(|&id| solver.segments[id].state.blocks.len() - 1)
+            .min()
+            .unwrap();
+        (solver, prev_segments, block_target)
+    }
+
+    #[test]
+    fn test_remote_blockbuilding() {
+        let addr = spawn_worker();
+        let (local, prev_segments, block_target) = stage1_solver();
+        let (distributed, _, _) = stage1_solver();
+        let distributed = distributed.with_workers([addr, addr]).unwrap();
+
+        let mut expected = local.blockbuild_shards(&prev_segments, block_target, 2, usize::MAX);
+        let mut actual = distributed.blockbuild_shards(&prev_segments, block_target, 2, usize::MAX);
+        assert!(!expected.is_empty());
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+
+        let limited = distributed.blockbuild_shards(&prev_segments, block_target, 2, 3);
+        assert_eq!(limited.len(), 3);
+    }
+
+    #[test]
+    fn test_deterministic_blockbuilding() {
+        let addr = spawn_worker();
+        let limit = 20;
+
+        let mut results = vec![];
+        for (thread_count, workers) in [(1, vec![]), (3, vec![]), (2, vec![addr])] {
+            let (solver, prev_segments, block_target) = stage1_solver();
+            let solver = solver
+                .with_deterministic(true)
+                .with_thread_count(thread_count)
+                .unwrap()
+                .with_workers(workers)
+                .unwrap();
+            results.push(
+                solver.install(|| solver.blockbuild_shards(&prev_segments, block_target, 2, limit)),
+            );
+        }
+
+        assert_eq!(results[0].len(), limit);
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0], results[2]);
+    }
+
+    #[test]
+    fn test_worker_connection_limit() {
+        let addr = spawn_worker();
+        let connections: Vec<Worker> = (0..MAX_CONNECTIONS)
+            .map(|_| Worker::connect(addr).unwrap())
+            .collect();
+        assert!(Worker::connect(addr).is_err());
+
+        // Once a coordinator disconnects, another one can connect.
+        drop(connections);
+        let reconnected = (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(10));
+            Worker::connect(addr).is_ok()
+        });
+        assert!(reconnected);
+    }
+}
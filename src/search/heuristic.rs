@@ -8,8 +8,14 @@ pub enum Heuristic {
     Fast,
     /// Prune conservatively; never prune a branch that could possibly result in
     /// a solution.
+    ///
+    /// Any block that a twist could split is assumed to pair with any other
+    /// block, so this prunes much less than [`Heuristic::Fast`].
     #[default]
     Correct,
+    /// Never prune. This is very slow and mostly useful for testing other
+    /// heuristics.
+    None,
 }
 
 impl Heuristic {
@@ -22,11 +28,17 @@ impl Heuristic {
         expected_blocks: usize,
         remaining_moves: usize,
     ) -> bool {
+        if self == Heuristic::None {
+            return true;
+        }
+
         let remaining_pairings_needed = state.blocks.len() - expected_blocks;
 
+        // The grip-theoretic limit assumes that only outer layers are twisted.
         remaining_pairings_needed <= self.combinatoric_limit(expected_blocks, remaining_moves)
-            && remaining_pairings_needed
-                <= self.grip_theoretic_limit(puzzle, state, remaining_moves)
+            && (puzzle.slice_twists
+                || remaining_pairings_needed
+                    <= self.grip_theoretic_limit(puzzle, state, remaining_moves))
     }
 
     /// Returns the maximum number of block pairings possible using a naive
@@ -35,6 +47,7 @@ impl Heuristic {
         match self {
             Heuristic::Fast => 1 << remaining_moves,
             Heuristic::Correct => (1 << remaining_moves) * expected_blocks,
+            Heuristic::None => usize::MAX,
         }
     }
     /// Returns the maximum number of block pairings using a grip-theoretic
@@ -56,17 +69,32 @@ impl Heuristic {
             // assume other moves are used to make more blocks.
             let mut max_blocks_solvable_using_b1 = remaining_moves.saturating_sub(2);
 
-            'b2: for (b2, b2_when_solved) in
-                std::iter::zip(state.blocks, blocks_when_solved).skip(i + 1)
-            {
+            // A block in the middle of a chain of merges might only be adjacent
+            // to blocks earlier in the list, so the correct heuristic has to
+            // check every other block.
+            let other_blocks = std::iter::zip(state.blocks, blocks_when_solved)
+                .enumerate()
+                .filter(|&(j, _)| j > i || (self == Heuristic::Correct && j != i))
+                .map(|(_, pair)| pair);
+            'b2: for (b2, b2_when_solved) in other_blocks {
+                // A twist can split a block, and then the two parts can merge
+                // with different blocks. That isn't accounted for below, so
+                // assume the worst. After a few random twists most blocks are
+                // splittable, so this limit rarely prunes anything for
+                // `Heuristic::Correct`; see `test_heuristic_prune_rates`.
+                if self == Heuristic::Correct && (is_splittable(b1) || is_splittable(b2)) {
+                    max_blocks_solvable_using_b1 = remaining_moves;
+                    break 'b2;
+                }
+
                 if let Some((_combined_block, merge_axis)) =
-                    b1_when_solved.try_merge_with(b2_when_solved)
+                    b1_when_solved.try_merge_with(b2_when_solved, false)
                 {
                     let ([body, head], head_when_solved) =
-                        if b1.layers().has_middle_slice_on_axis(merge_axis) {
-                            ([b1, b2], b1_when_solved)
+                        if b1_when_solved.has_middle_slice_on_axis(merge_axis) {
+                            ([b1, b2], b2_when_solved)
                         } else {
-                            ([b2, b1], b2_when_solved)
+                            ([b2, b1], b1_when_solved)
                         };
 
                     let [g1, g2] = GripId::pair_on_axis(merge_axis);
@@ -115,3 +143,219 @@ impl Heuristic {
         max_pairings_possible
     }
 }
+
+/// Returns whether some twist splits `block` into two blocks.
+fn is_splittable(block: Block) -> bool {
+    HYPERCUBE_GRIPS
+        .into_iter()
+        .any(|g| block.layers().grip_status(g) == GripStatus::Blocked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// Returns the twist sequences that reduce `state` to at most
+    /// `expected_blocks` blocks within `depth` twists.
+    fn solutions(
+        params: BlockBuildingSearchParams,
+        state: BlockSet,
+        expected_blocks: usize,
+        depth: usize,
+    ) -> BTreeSet<Vec<Twist>> {
+        let mut results = vec![];
+        let init = Segment {
+            state,
+            ..Default::default()
+        };
+        dfs_blockbuild(
+            params,
+            &RUBIKS_4D,
+            expected_blocks,
+            depth,
+            &mut results,
+            init,
+            None,
+            0,
+        );
+        results
+            .into_iter()
+            .map(|s| s.segment_twists.to_vec())
+            .collect()
+    }
+
+    fn params(heuristic: Heuristic) -> BlockBuildingSearchParams {
+        BlockBuildingSearchParams {
+            heuristic,
+            ..Default::default()
+        }
+    }
+
+    /// Returns a state like the ones at the start of stages 1 and 4.
+    ///
+    /// For stage 4, twists in `scramble` that would move the first block are
+    /// skipped so that the new pieces are consistent with it.
+    fn scrambled_state(stage_index: usize, scramble: &[Twist]) -> BlockSet {
+        let first_block = Block::new_solved([], [R, U, F, O]).unwrap();
+        let second_block = Block::new_solved([R], [U, F, O]).unwrap();
+        let state = BlockSet::default();
+        match stage_index {
+            0 => state.add_block_with_setup_moves(&RUBIKS_4D, scramble, first_block),
+            _ => {
+                let scramble = scramble
+                    .iter()
+                    .copied()
+                    .filter(|twist| {
+                        first_block.layers().grip_status(twist.grip()) == GripStatus::Inactive
+                    })
+                    .collect_vec();
+                state
+                    .add_block_with_setup_moves(&RUBIKS_4D, &[], first_block)
+                    .unwrap()
+                    .add_block_with_setup_moves(&RUBIKS_4D, &scramble, second_block)
+            }
+        }
+        .unwrap()
+    }
+
+    fn check_correct_heuristic(
+        stage_index: usize,
+        scramble: Vec<usize>,
+        depth: usize,
+        pairings: usize,
+    ) -> Result<(), TestCaseError> {
+        let scramble: Vec<Twist> = scramble.into_iter().map(|i| RUBIKS_4D.twists[i]).collect();
+        let state = scrambled_state(stage_index, &scramble);
+        let expected_blocks = state.blocks.len().saturating_sub(pairings);
+
+        let all = solutions(params(Heuristic::None), state, expected_blocks, depth);
+        let correct = solutions(params(Heuristic::Correct), state, expected_blocks, depth);
+        prop_assert_eq!(&all, &correct);
+
+        let with_pattern_database = BlockBuildingSearchParams {
+            pattern_database: Some(&*PATTERN_DATABASE_4D),
+            ..params(Heuristic::Correct)
+        };
+        let correct = solutions(with_pattern_database, state, expected_blocks, depth);
+        prop_assert_eq!(&all, &correct);
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(96))]
+
+        #[test]
+        fn proptest_correct_heuristic_never_prunes_solutions(
+            stage_index in 0..2_usize,
+            scramble in prop::collection::vec(0..RUBIKS_4D.twists.len(), 0..40),
+            depth in 1..=2_usize,
+            pairings in 1..=2_usize,
+        ) {
+            check_correct_heuristic(stage_index, scramble, depth, pairings)?;
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        /// Searching 3 moves deep without pruning takes about a minute per
+        /// case in a debug build. Run with `cargo test --release -- --ignored`.
+        #[test]
+        #[ignore]
+        fn proptest_correct_heuristic_never_prunes_deep_solutions(
+            stage_index in 0..2_usize,
+            scramble in prop::collection::vec(0..RUBIKS_4D.twists.len(), 0..40),
+            pairings in 1..=3_usize,
+        ) {
+            check_correct_heuristic(stage_index, scramble, 3, pairings)?;
+        }
+    }
+
+    /// Measures how many states each heuristic prunes one move before a
+    /// pairing, and how many two-move solutions [`Heuristic::Fast`] misses.
+    #[test]
+    fn test_heuristic_prune_rates() {
+        let db = &*PATTERN_DATABASE_4D;
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        let mut unsolvable = 0;
+        let mut correct_pruned = 0;
+        let mut db_pruned = 0;
+        let mut fast_pruned = 0;
+        let mut total = 0;
+        let mut missed = 0;
+        for i in 0..40 {
+            let scramble = RUBIKS_4D.random_moves(&mut rng, 40);
+            let state = scrambled_state(i % 2, &scramble);
+            let expected_blocks = state.blocks.len() - 1;
+
+            let solvable =
+                !solutions(params(Heuristic::None), state, expected_blocks, 1).is_empty();
+            let prunes = |h: Heuristic| !h.might_be_solvable(&RUBIKS_4D, state, expected_blocks, 1);
+            let db_prunes = db.min_block_count(state, 1) > expected_blocks;
+            assert!(!(solvable && prunes(Heuristic::Correct)));
+            assert!(!(solvable && db_prunes));
+            unsolvable += !solvable as usize;
+            correct_pruned += prunes(Heuristic::Correct) as usize;
+            db_pruned += db_prunes as usize;
+            fast_pruned += prunes(Heuristic::Fast) as usize;
+
+            if i >= 12 {
+                continue;
+            }
+            let all = solutions(params(Heuristic::None), state, expected_blocks, 2);
+            let fast = solutions(params(Heuristic::Fast), state, expected_blocks, 2);
+            assert!(fast.is_subset(&all));
+            total += all.len();
+            missed += all.len() - fast.len();
+        }
+
+        assert!(
+            db_pruned >= correct_pruned,
+            "pattern database pruned {db_pruned}, correct pruned {correct_pruned}"
+        );
+        assert!(
+            fast_pruned * 2 >= unsolvable,
+            "fast pruned {fast_pruned}/{unsolvable}"
+        );
+        assert!(
+            missed * 1000 <= total,
+            "fast missed {missed}/{total} solutions"
+        );
+    }
+
+    /// [`Heuristic::Correct`] gives up on every state with a splittable block,
+    /// but it prunes pieces that are too far apart to pair.
+    #[test]
+    fn test_correct_heuristic_prunes_unsplittable_blocks() {
+        let db = &*PATTERN_DATABASE_4D;
+        let corner = Piece::new_solved([R, U, F, O]);
+        let edge = Piece::new_solved([R, U, F]);
+        let corner_grips = GripSet::from_iter([R, U, F, O]);
+        let edge_grips = GripSet::from_iter([R, U, F]);
+
+        // Twist the corner away from the edge so that they take 2 twists to
+        // pair.
+        let attitude = itertools::iproduct!(&RUBIKS_4D.twists, &RUBIKS_4D.twists)
+            .map(|(t1, t2)| t2.transform * t1.transform)
+            .find(|&a| db.pair_distance((corner_grips, a), (edge_grips, IDENT)) == Some(2))
+            .unwrap();
+        let state = BlockSet {
+            blocks: crate::StackVec::from_iter([Block::from(attitude * corner), Block::from(edge)])
+                .unwrap(),
+        };
+        assert!(!state.blocks.iter().any(|&b| is_splittable(b)));
+
+        assert!(solutions(params(Heuristic::None), state, 1, 1).is_empty());
+        assert!(!Heuristic::Correct.might_be_solvable(&RUBIKS_4D, state, 1, 1));
+        assert!(db.min_block_count(state, 1) > 1);
+
+        assert!(!solutions(params(Heuristic::None), state, 1, 2).is_empty());
+        assert!(Heuristic::Correct.might_be_solvable(&RUBIKS_4D, state, 1, 2));
+        assert_eq!(db.min_block_count(state, 2), 1);
+    }
+}
@@ -1,12 +1,13 @@
 use itertools::Itertools;
 
+use super::checkpoint::Encode;
 use crate::sim::*;
 
 /// Metadata about a particular solution.
 ///
 /// This mainly includes info about what order we are using to solve grips,
 /// which helps when figuring out the next blocks to solve.
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SolutionMetadata {
     stage: u16,
     first_block: Block,
@@ -18,6 +19,44 @@ pub struct SolutionMetadata {
     front_grip: Option<GripId>, // third-to-last grip (front grip in typical 3-block)
 }
 
+impl TransformByElem for SolutionMetadata {
+    fn transform_by(self, elem: ElemId) -> Self {
+        Self {
+            stage: self.stage,
+            first_block: elem.transform(self.first_block),
+            second_block: elem.transform(self.second_block),
+            third_block: elem.transform(self.third_block),
+
+            last_layer: self.last_layer.map(|g| elem * g),
+            right_grip: self.right_grip.map(|g| elem * g),
+            front_grip: self.front_grip.map(|g| elem * g),
+        }
+    }
+}
+
+impl Encode for SolutionMetadata {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.stage.encode(out);
+        self.first_block.encode(out);
+        self.second_block.encode(out);
+        self.third_block.encode(out);
+        self.last_layer.encode(out);
+        self.right_grip.encode(out);
+        self.front_grip.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            stage: u16::decode(input)?,
+            first_block: Block::decode(input)?,
+            second_block: Block::decode(input)?,
+            third_block: Block::decode(input)?,
+            last_layer: Option::decode(input)?,
+            right_grip: Option::decode(input)?,
+            front_grip: Option::decode(input)?,
+        })
+    }
+}
+
 impl SolutionMetadata {
     fn next_stage(mut self) -> Self {
         self.stage += 1;
@@ -61,6 +100,12 @@ impl SolutionMetadata {
         (block, self)
     }
 
+    /// Returns the block that the final stage solves, which contains every
+    /// piece except the last layer.
+    pub fn goal_block(self) -> Block {
+        Block::new_solved([], [self.last_layer()]).unwrap()
+    }
+
     pub fn stage1(self) -> impl IntoIterator<Item = (Block, Self)> {
         assert_eq!(self.stage, 0);
         itertools::iproduct!([R, L], [U, D], [F, B], [I, O])
@@ -0,0 +1,322 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use itertools::Itertools;
+
+use crate::mc4d::ClickType;
+use crate::sim::*;
+
+/// Way of counting the length of a twist sequence, which the solver minimizes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Metric {
+    /// Execution turn metric: every twist counts as 1. Rotations of the whole
+    /// puzzle are free in this metric and the next two.
+    Etm,
+    /// Slice turn metric: every twist counts as 1, except that consecutive
+    /// twists of the same layers on the same grip count as 1 together.
+    #[default]
+    Stm,
+    /// Quarter turn metric: every twist counts as the fewest 90° twists on the
+    /// same grip that it could be replaced with. For example, `IU` counts as 1,
+    /// `IU2` and `IUFR` count as 2, and `IUR` counts as 3.
+    Qtm,
+    /// Rotation turn metric: like ETM, except that rotations of the whole
+    /// puzzle also count as 1.
+    Rtm,
+    /// Estimated time to execute each twist, according to a [`CostModel`].
+    Cost(&'static CostModel),
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Etm => write!(f, "ETM"),
+            Metric::Stm => write!(f, "STM"),
+            Metric::Qtm => write!(f, "QTM"),
+            Metric::Rtm => write!(f, "RTM"),
+            Metric::Cost(_) => write!(f, "cost"),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "ETM" => Ok(Metric::Etm),
+            "STM" => Ok(Metric::Stm),
+            "QTM" => Ok(Metric::Qtm),
+            "RTM" => Ok(Metric::Rtm),
+            _ => Err("unknown metric; expected ETM, STM, QTM, or RTM"),
+        }
+    }
+}
+
+impl Metric {
+    /// Returns the cost of `twist` after `last_twist`.
+    #[inline]
+    pub fn twist_cost(self, twist: Twist, last_twist: Option<Twist>) -> usize {
+        match self {
+            Metric::Etm | Metric::Stm | Metric::Qtm if twist.is_rotation() => 0,
+            Metric::Etm | Metric::Rtm => 1,
+            Metric::Stm => last_twist
+                .is_none_or(|last| (last.grip(), last.layers()) != (twist.grip(), twist.layers()))
+                as usize,
+            Metric::Qtm => {
+                QUARTER_TURN_COUNTS[twist.grip().id() as usize][twist.transform.id() as usize]
+                    as usize
+            }
+            Metric::Cost(cost_model) => cost_model.twist_cost(twist),
+        }
+    }
+
+    /// Returns the length of `twists`.
+    pub fn count(self, twists: &[Twist]) -> usize {
+        let last_twists = std::iter::once(None).chain(twists.iter().copied().map(Some));
+        std::iter::zip(twists, last_twists)
+            .map(|(&twist, last_twist)| self.twist_cost(twist, last_twist))
+            .sum()
+    }
+}
+
+/// Estimated time to execute each twist, in arbitrary units.
+///
+/// Each twist starts with the cost of the [`ClickType`] used to do it in MC4D,
+/// which is 10 by default, and may be given its own cost to account for twists
+/// that are easier or harder to do than others with the same click type, or
+/// for keybinds in Hyperspeedcube.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CostModel {
+    /// Cost of each twist, indexed by grip ID and then by transform ID.
+    costs: [[u16; 256]; 8],
+    /// Cost of every rotation of the whole puzzle.
+    rotation_cost: u16,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        let mut ret = Self {
+            costs: [[0; 256]; 8],
+            rotation_cost: 0,
+        };
+        for &twist in &RUBIKS_4D.twists {
+            ret = ret.with_twist_cost(twist, Self::DEFAULT_CLICK_COST);
+        }
+        ret
+    }
+}
+
+impl FromStr for CostModel {
+    type Err = &'static str;
+
+    /// Parses a cost model with one `name = cost` entry per line, where `name`
+    /// is a click type (`ridge`, `ridge2`, `edge`, or `corner`) or a twist
+    /// name such as `IU`, or `rotation` for rotations of the whole puzzle.
+    /// Entries for twists override entries for click types regardless of
+    /// order. Blank lines and `#` comments are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut click_costs = vec![];
+        let mut twist_costs = vec![];
+        let mut rotation_cost = 0;
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, cost) = line.split_once('=').ok_or("expected `name = cost`")?;
+            let (name, cost) = (name.trim(), cost.trim());
+            let cost = cost.parse().map_err(|_| "invalid cost")?;
+            if name == "rotation" {
+                rotation_cost = cost;
+            } else if let Ok(click_type) = name.parse::<ClickType>() {
+                click_costs.push((click_type, cost));
+            } else if let Some(&twist) = TWISTS_FROM_NAME.get(name) {
+                twist_costs.push((twist, cost));
+            } else {
+                return Err("expected click type or twist name");
+            }
+        }
+
+        let mut ret = Self::default().with_rotation_cost(rotation_cost);
+        for (click_type, cost) in click_costs {
+            ret = ret.with_click_cost(click_type, cost);
+        }
+        for (twist, cost) in twist_costs {
+            ret = ret.with_twist_cost(twist, cost);
+        }
+        Ok(ret)
+    }
+}
+
+impl CostModel {
+    /// Default cost of each click type.
+    pub const DEFAULT_CLICK_COST: u16 = 10;
+
+    /// Maximum number of different cost models that [`Self::intern()`] will
+    /// leak, so that a worker can't be made to leak unbounded memory.
+    pub const MAX_INTERNED: usize = 64;
+
+    /// Sets the cost of every twist done with `click_type`, overriding any
+    /// cost set for those twists before.
+    pub fn with_click_cost(mut self, click_type: ClickType, cost: u16) -> Self {
+        for &twist in &RUBIKS_4D.twists {
+            if ClickType::of(twist) == Some(click_type) {
+                self = self.with_twist_cost(twist, cost);
+            }
+        }
+        self
+    }
+
+    /// Sets the cost of `twist`.
+    pub fn with_twist_cost(mut self, twist: Twist, cost: u16) -> Self {
+        self.costs[twist.grip().id() as usize][twist.transform.id() as usize] = cost;
+        self
+    }
+
+    /// Sets the cost of every rotation of the whole puzzle, which is 0 by
+    /// default.
+    pub fn with_rotation_cost(mut self, cost: u16) -> Self {
+        self.rotation_cost = cost;
+        self
+    }
+
+    /// Returns the cost of `twist`. Slice and wide twists cost the same as
+    /// the outer-layer twist.
+    #[inline]
+    pub fn twist_cost(&self, twist: Twist) -> usize {
+        if twist.is_rotation() {
+            return self.rotation_cost as usize;
+        }
+        self.costs[twist.grip().id() as usize][twist.transform.id() as usize] as usize
+    }
+
+    /// Returns the cost of every rotation of the whole puzzle.
+    pub fn rotation_cost(&self) -> u16 {
+        self.rotation_cost
+    }
+
+    /// Returns a `'static` reference to an equal cost model, for use in
+    /// [`Metric::Cost`]. The cost model is leaked unless an equal one has
+    /// already been. Returns an error if [`Self::MAX_INTERNED`] other cost
+    /// models have already been leaked.
+    pub fn intern(self) -> Result<&'static Self, &'static str> {
+        static INTERNED: Mutex<Vec<&'static CostModel>> = Mutex::new(vec![]);
+
+        let mut interned = INTERNED.lock().unwrap();
+        if let Some(&existing) = interned.iter().find(|&&m| *m == self) {
+            return Ok(existing);
+        }
+        if interned.len() >= Self::MAX_INTERNED {
+            return Err("too many different cost models");
+        }
+        let leaked = Box::leak(Box::new(self));
+        interned.push(leaked);
+        Ok(leaked)
+    }
+}
+
+/// Fewest 90° twists needed to make each twist, indexed by grip ID and then by
+/// transform ID.
+#[static_init::dynamic]
+static QUARTER_TURN_COUNTS: [[u8; 256]; 8] = {
+    let mut ret = [[u8::MAX; 256]; 8];
+    for grip in &RUBIKS_4D.grips {
+        let counts = &mut ret[grip.id.id() as usize];
+
+        let quarter_turns = (grip.transforms.iter().copied())
+            .filter(|&t| t != IDENT && t * t != IDENT && t * t * t * t == IDENT)
+            .collect_vec();
+
+        // Breadth-first search from the identity
+        counts[IDENT.id() as usize] = 0;
+        let mut frontier = vec![IDENT];
+        let mut distance = 0;
+        while !frontier.is_empty() {
+            distance += 1;
+            let mut next_frontier = vec![];
+            for t in frontier {
+                for &q in &quarter_turns {
+                    let new = q * t;
+                    if counts[new.id() as usize] == u8::MAX {
+                        counts[new.id() as usize] = distance;
+                        next_frontier.push(new);
+                    }
+                }
+            }
+            frontier = next_frontier;
+        }
+    }
+    ret
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let twists = crate::parse_twists("IU IU2 IU IUR IUFR RU");
+        assert_eq!(Metric::Etm.count(&twists), 6);
+        assert_eq!(Metric::Rtm.count(&twists), 6);
+        assert_eq!(Metric::Stm.count(&twists), 2);
+        assert_eq!(Metric::Qtm.count(&twists), 1 + 2 + 1 + 3 + 2 + 1);
+
+        // Twists of different layers on the same grip count separately in STM.
+        let twists = crate::parse_twists("IU {2}IU {2}IU2 {1-2}IU");
+        assert_eq!(Metric::Stm.count(&twists), 3);
+
+        // Only RTM counts rotations.
+        let twists = crate::parse_twists("IU {1-3}RU IU");
+        assert_eq!(Metric::Etm.count(&twists), 2);
+        assert_eq!(Metric::Stm.count(&twists), 2);
+        assert_eq!(Metric::Qtm.count(&twists), 2);
+        assert_eq!(Metric::Rtm.count(&twists), 3);
+
+        for twist in &RUBIKS_4D.twists {
+            let qtm = Metric::Qtm.twist_cost(*twist, None);
+            assert_eq!(qtm, Metric::Qtm.twist_cost(twist.inv(), None));
+            assert!((1..=3).contains(&qtm), "{twist} has QTM {qtm}");
+        }
+
+        for metric in [Metric::Etm, Metric::Stm, Metric::Qtm, Metric::Rtm] {
+            assert_eq!(metric.to_string().parse(), Ok(metric));
+        }
+    }
+
+    #[test]
+    fn test_cost_model() {
+        let cost_model: CostModel = "
+            # slower clicks
+            ridge2 = 12
+            corner = 15
+            IU = 8 # easy to reach
+            edge = 13
+            rotation = 5
+        "
+        .parse()
+        .unwrap();
+        let metric = Metric::Cost(cost_model.clone().intern().unwrap());
+
+        let twists = crate::parse_twists("IU IU2 IUR IUFR RU");
+        assert_eq!(metric.count(&twists), 8 + 12 + 13 + 15 + 10);
+        assert_eq!(metric.count(&crate::parse_twists("{1-3}RU")), 5);
+        assert_eq!(
+            ClickType::of(TWISTS_FROM_NAME["RU"]),
+            Some(ClickType::Ridge),
+        );
+
+        for twist in &RUBIKS_4D.twists {
+            assert!(ClickType::of(*twist).is_some(), "{twist} is not one click");
+        }
+
+        assert!(std::ptr::eq(
+            cost_model.clone().intern().unwrap(),
+            cost_model.intern().unwrap()
+        ));
+        assert!("IU 8".parse::<CostModel>().is_err());
+        assert!("IX = 8".parse::<CostModel>().is_err());
+        assert!("IU = -1".parse::<CostModel>().is_err());
+    }
+}
@@ -1,17 +1,34 @@
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::atomic::AtomicIsize;
 
 use itertools::Itertools;
 use rayon::prelude::*;
 
+mod automaton;
+mod bidirectional;
+mod checkpoint;
+mod distributed;
 mod heuristic;
 mod meta;
+mod metric;
 mod params;
+mod pattern_db;
+mod ranking;
 mod segment;
+mod symmetry;
 
+pub use automaton::TwistSeqState;
+pub use bidirectional::{BackwardTable, dfs_bidirectional};
+pub use distributed::run_worker;
 pub use heuristic::Heuristic;
-use meta::SolutionMetadata;
+pub use meta::SolutionMetadata;
+pub use metric::{CostModel, Metric};
 pub use params::BlockBuildingSearchParams;
+pub use pattern_db::{PATTERN_DATABASE_4D, PatternDatabase, PatternDatabaseSource};
+pub use ranking::{Candidate, pareto_front};
 pub use segment::{Segment, SegmentId, SegmentStore};
+pub use symmetry::{CanonicalKey, canonicalize};
 
 use crate::sim::*;
 use crate::{MAX_SOLUTION_COUNT, Profile};
@@ -21,84 +38,293 @@ pub struct Solver {
     puzzle: &'static Puzzle,
     params: BlockBuildingSearchParams,
     segments: SegmentStore,
+    /// Number of stages that have been completed.
+    stage: usize,
+    /// File to save a checkpoint to after each stage.
+    checkpoint_path: Option<PathBuf>,
+    /// Worker processes to split blockbuilding steps with.
+    workers: Vec<distributed::Worker>,
+    /// Thread pool to run parallel work in, or `None` to use the global rayon
+    /// thread pool.
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    /// Comparison to rank the final solutions by, or `None` to rank them by
+    /// [`Candidate::cmp_by_length()`].
+    ranking: Option<Box<ranking::Ranking>>,
+    /// Whether to keep only the Pareto-optimal final solutions.
+    pareto_front: bool,
 }
 impl Solver {
+    /// Number of stages in the solve.
+    const STAGE_COUNT: usize = 6;
+
     pub fn new(profile: Profile, scramble: impl Into<Vec<Twist>>) -> Self {
+        Self {
+            segments: SegmentStore::new(scramble.into()),
+            ..Self::from_state(profile, PuzzleState::default())
+        }
+    }
+
+    /// Constructs a solver that starts from `state` instead of a scramble, for
+    /// example from [`StickerState`] input. Pieces are found by where they are
+    /// in `state`.
+    pub fn from_state(profile: Profile, state: PuzzleState) -> Self {
         Self {
             profile,
             puzzle: &*RUBIKS_4D,
             params: BlockBuildingSearchParams {
                 heuristic: Heuristic::Fast,
+                metric: Metric::Stm,
                 max_depth: 4,
-                parallel_depth: 2,
+                parallel_depth: None,
+                pattern_database: None,
+                bidirectional_depth: 0,
+                deterministic: false,
                 verbosity: 2,
             },
-            segments: SegmentStore::new(scramble.into()),
+            segments: SegmentStore::from_state(state),
+            stage: 0,
+            checkpoint_path: None,
+            workers: vec![],
+            thread_pool: None,
+            ranking: None,
+            pareto_front: false,
         }
     }
 
-    pub fn solve(mut self) -> Vec<Twist> {
-        let start = std::time::Instant::now();
+    /// Saves a checkpoint to `path` after each stage. See
+    /// [`Solver::save_checkpoint()`].
+    pub fn with_checkpoint_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
 
-        // Keep the call graph flat for recursion.
+    /// Returns the scramble being solved, which is empty if the solver was
+    /// constructed with [`Solver::from_state()`].
+    pub fn scramble(&self) -> &[Twist] {
+        &self.segments.scramble
+    }
 
-        println!("\nSTAGE 1: mid + left, 2x2x2x2 block");
-        self.do_blockbuilding_stage(self.profile.select(1, 5), |meta| meta.stage1());
+    /// Returns the state of the puzzle after the scramble, which is the state
+    /// that the solution starts from.
+    pub fn scrambled_state(&self) -> PuzzleState {
+        self.segments.scrambled_state()
+    }
 
-        println!("\nSTAGE 2: mid + left, 2x2x3x2 block");
-        self.do_blockbuilding_stage(self.profile.select(1, 5), |meta| meta.stage2());
+    /// Uses meet-in-the-middle search for the final stage, searching `depth`
+    /// twists backward from the goal. See
+    /// [`BlockBuildingSearchParams::bidirectional_depth`].
+    pub fn with_bidirectional_depth(mut self, depth: usize) -> Self {
+        self.params.bidirectional_depth = depth;
+        self
+    }
 
-        println!("\nSTAGE 3: mid + left, 2x3x3x2 block");
-        self.do_blockbuilding_stage(self.profile.select(2, 6), |meta| meta.stage3());
+    /// Runs all parallel work in `thread_pool` instead of the global rayon
+    /// thread pool.
+    pub fn with_thread_pool(mut self, thread_pool: Arc<rayon::ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
 
-        println!("\nSTAGE 4: right (mid + left), 2x2x2x1 block");
-        self.do_blockbuilding_stage(self.profile.select(2, 6), |meta| meta.stage4());
+    /// Runs all parallel work in a new thread pool with `thread_count` threads
+    /// instead of the global rayon thread pool.
+    pub fn with_thread_count(
+        self,
+        thread_count: usize,
+    ) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(thread_count)
+            .build()?;
+        Ok(self.with_thread_pool(Arc::new(thread_pool)))
+    }
 
-        println!("\nSTAGE 5: right (mid + left), 2x2x3x1 block");
-        self.do_blockbuilding_stage(self.profile.select(2, 5), |meta| meta.stage5());
+    /// Parallelizes each search to `depth` instead of choosing a depth based
+    /// on the number of threads. See
+    /// [`BlockBuildingSearchParams::parallel_depth`].
+    pub fn with_parallel_depth(mut self, depth: usize) -> Self {
+        self.params.parallel_depth = Some(depth);
+        self
+    }
 
-        println!("\nSTAGE 6: F2L");
-        self.do_blockbuilding_stage(self.profile.select(1, 1), |meta| meta.stage6());
+    /// Minimizes solution length in `metric` instead of STM.
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.params.metric = metric;
+        self
+    }
 
-        println!("\nTotal elapsed time: {:?}", start.elapsed());
+    /// Searches with slice and wide twists in addition to outer-layer twists,
+    /// and merges blocks that are only connected through the middle layer.
+    /// See [`RUBIKS_4D_WITH_SLICES`].
+    ///
+    /// The pattern database and the grip-theoretic heuristic assume outer-layer
+    /// twists, so they aren't used.
+    pub fn with_slice_twists(mut self, slice_twists: bool) -> Self {
+        self.puzzle = rubiks_4d(slice_twists, self.puzzle.rotation_twists);
+        self
+    }
 
-        println!();
-        let best_solution = *self
-            .segments
-            .best_solutions_so_far()
-            .unwrap()
-            .first()
-            .unwrap();
-        println!("Best solution: {}", self.segments[best_solution]);
-        let twists_of_best_solution = self.segments.solution_twists_for_segment(best_solution);
-        println!("{}", twists_of_best_solution.iter().join(" "));
+    /// Searches with rotations of the whole puzzle as moves, so that a
+    /// solution can rotate the puzzle to use a twist that is cheaper in the
+    /// solver's [`Metric`]. Rotations are free except in [`Metric::Rtm`] and
+    /// [`Metric::Cost`]. Rotations that fix no grip are only used with a twist
+    /// in between; see [`RUBIKS_4D_WITH_ROTATIONS`].
+    pub fn with_rotations(mut self, rotations: bool) -> Self {
+        self.puzzle = rubiks_4d(self.puzzle.slice_twists, rotations);
+        self
+    }
 
-        let mut initial_state = PuzzleState::default();
-        initial_state.do_twists(&self.segments.scramble);
+    /// Prunes the search with `pattern_database`, such as
+    /// [`PATTERN_DATABASE_4D`] or one loaded with
+    /// [`PatternDatabase::load_or_build()`]. Workers use their own
+    /// [`PATTERN_DATABASE_4D`], which is the same. See
+    /// [`BlockBuildingSearchParams::pattern_database`].
+    pub fn with_pattern_database(
+        mut self,
+        pattern_database: Option<&'static PatternDatabase>,
+    ) -> Self {
+        self.params.pattern_database = pattern_database;
+        self
+    }
+
+    /// Gives the same solution for the same scramble and parameters,
+    /// regardless of the number of threads or workers. See
+    /// [`BlockBuildingSearchParams::deterministic`].
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.params.deterministic = deterministic;
+        self
+    }
+
+    /// Ranks the final solutions by `key`, from lowest to highest, instead of
+    /// by length and then by unoriented pieces. Ties are broken by the default
+    /// ranking.
+    pub fn with_ranking<K: Ord>(
+        mut self,
+        key: impl Fn(&Candidate) -> K + Send + Sync + 'static,
+    ) -> Self {
+        self.ranking = Some(Box::new(move |a, b| key(a).cmp(&key(b))));
+        self
+    }
+
+    /// Keeps only the final solutions that are Pareto-optimal over length and
+    /// unoriented pieces. See [`pareto_front()`].
+    pub fn with_pareto_front(mut self, pareto_front: bool) -> Self {
+        self.pareto_front = pareto_front;
+        self
+    }
+
+    /// Runs `op` in the solver's thread pool.
+    fn install<R: Send>(&self, op: impl Send + FnOnce() -> R) -> R {
+        match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(op),
+            None => op(),
+        }
+    }
+
+    pub fn solve(self) -> Vec<Twist> {
+        match self.thread_pool.clone() {
+            Some(thread_pool) => thread_pool.install(|| self.solve_in_current_thread_pool()),
+            None => self.solve_in_current_thread_pool(),
+        }
+    }
+
+    fn solve_in_current_thread_pool(mut self) -> Vec<Twist> {
+        let start = std::time::Instant::now();
 
-        let all_solutions = self
+        if self.stage > 0 {
+            println!("\nResuming after stage {}", self.stage);
+        }
+
+        // Keep the call graph flat for recursion.
+        while self.stage < Self::STAGE_COUNT {
+            match self.stage {
+                0 => {
+                    println!("\nSTAGE 1: mid + left, 2x2x2x2 block");
+                    self.do_blockbuilding_stage(self.profile.select(1, 5), |meta| meta.stage1());
+                }
+                1 => {
+                    println!("\nSTAGE 2: mid + left, 2x2x3x2 block");
+                    self.do_blockbuilding_stage(self.profile.select(1, 5), |meta| meta.stage2());
+                }
+                2 => {
+                    println!("\nSTAGE 3: mid + left, 2x3x3x2 block");
+                    self.do_blockbuilding_stage(self.profile.select(2, 6), |meta| meta.stage3());
+                }
+                3 => {
+                    println!("\nSTAGE 4: right (mid + left), 2x2x2x1 block");
+                    self.do_blockbuilding_stage(self.profile.select(2, 6), |meta| meta.stage4());
+                }
+                4 => {
+                    println!("\nSTAGE 5: right (mid + left), 2x2x3x1 block");
+                    self.do_blockbuilding_stage(self.profile.select(2, 5), |meta| meta.stage5());
+                }
+                _ => {
+                    println!("\nSTAGE 6: F2L");
+                    if self.params.bidirectional_depth > 0 {
+                        self.do_bidirectional_stage(|meta| meta.stage6());
+                    } else {
+                        self.do_blockbuilding_stage(self.profile.select(1, 1), |meta| {
+                            meta.stage6()
+                        });
+                    }
+                }
+            }
+            self.stage += 1;
+
+            if let Some(path) = &self.checkpoint_path {
+                match self.save_checkpoint(path) {
+                    Ok(()) => println!("  Saved checkpoint to {}", path.display()),
+                    Err(e) => println!("  WARNING: unable to save checkpoint: {e}"),
+                }
+            }
+        }
+
+        println!("\nTotal elapsed time: {:?}", start.elapsed());
+
+        let initial_state = self.segments.scrambled_state();
+
+        let mut candidates = self
             .segments
             .best_solutions_so_far()
             .unwrap()
             .iter()
             .map(|&id| {
-                let segment = &self.segments[id];
                 let twists = self.segments.solution_twists_for_segment(id);
                 let mut state = initial_state.clone();
                 state.do_twists(&twists);
-                let orientation_score = state.unoriented_pieces(segment.meta.last_layer());
-                (twists.len(), orientation_score, twists)
+                let length = self.params.metric.count(&twists);
+                Candidate::new(twists, state, self.segments[id].meta, length)
             })
-            .sorted();
+            .collect_vec();
+        if self.pareto_front {
+            candidates = pareto_front(candidates);
+        }
+        candidates.sort_by(Candidate::cmp_by_length);
+        if let Some(ranking) = &self.ranking {
+            candidates.sort_by(ranking); // stable, so ties stay in default order
+        }
+
+        println!();
+        let best_solution = candidates.first().unwrap();
+        println!(
+            "Best solution: {} {} with {:?} unoriented pieces",
+            best_solution.length, self.params.metric, best_solution.unoriented_pieces,
+        );
+        println!("{}", best_solution.twists.iter().join(" "));
+        let twists_of_best_solution = best_solution.twists.clone();
 
         let out_file_name = "out.txt";
         std::fs::write(
             out_file_name,
-            all_solutions
+            candidates
                 .into_iter()
-                .map(|(twist_count, orientation_score, twists)| {
+                .map(|candidate| {
+                    let Candidate {
+                        length,
+                        unoriented_pieces,
+                        twists,
+                        ..
+                    } = candidate;
                     let twists_str = twists.iter().join(" ");
-                    format!("{twist_count:3} {orientation_score:2?}    {twists_str}")
+                    format!("{length:3} {unoriented_pieces:2?}    {twists_str}")
                 })
                 .join("\n"),
         )
@@ -115,6 +341,47 @@ impl Solver {
     ) {
         let t = std::time::Instant::now();
 
+        let init_blocks = self.do_add_pieces_step(make_target_blocks);
+
+        // Blockbuild
+        for target in (target_block_count..init_blocks).rev() {
+            self.do_blockbuilding_step(target);
+            // if self.steps.last().unwrap().is_empty() {
+            //     log!(self.params, 1, "No solutions! Giving up ...");
+            //     std::process::exit(1);
+            // }
+        }
+
+        println!("  Completed stage in {:?}", t.elapsed());
+    }
+
+    /// Same as [`Self::do_blockbuilding_stage()`] with a target of one block,
+    /// but uses meet-in-the-middle search to pair the last two blocks.
+    fn do_bidirectional_stage<I: IntoIterator<Item = (Block, SolutionMetadata)>>(
+        &mut self,
+        make_target_blocks: impl Send + Sync + Fn(SolutionMetadata) -> I,
+    ) {
+        let t = std::time::Instant::now();
+
+        let init_blocks = self.do_add_pieces_step(make_target_blocks);
+
+        for target in (2..init_blocks).rev() {
+            self.do_blockbuilding_step(target);
+        }
+        if !self.do_bidirectional_step() {
+            println!("  WARNING: bidirectional search failed; falling back to blockbuilding");
+            self.do_blockbuilding_step(1);
+        }
+
+        println!("  Completed stage in {:?}", t.elapsed());
+    }
+
+    /// Adds the pieces for the next stage to every segment and returns the
+    /// maximum number of blocks in any of the new segments.
+    fn do_add_pieces_step<I: IntoIterator<Item = (Block, SolutionMetadata)>>(
+        &mut self,
+        make_target_blocks: impl Send + Sync + Fn(SolutionMetadata) -> I,
+    ) -> usize {
         let step = self.segments.next_step();
 
         // Add pieces
@@ -123,13 +390,17 @@ impl Solver {
                 .par_iter()
                 .flat_map(|&prev_segment_id| {
                     let prev_segment = &this.segments[prev_segment_id];
+                    let initial_state = &this.segments.initial_state;
+                    let setup_moves = this.segments.all_prior_twists_for_segment(prev_segment_id);
                     let mut results = vec![];
                     for (new_block, new_meta) in make_target_blocks(prev_segment.meta) {
-                        let setup_moves =
-                            this.segments.all_prior_twists_for_segment(prev_segment_id);
-                        if let Some(new_segment) =
-                            prev_segment.push_block(this.puzzle, &setup_moves, new_block, new_meta)
-                        {
+                        if let Some(new_segment) = prev_segment.push_block(
+                            this.puzzle,
+                            initial_state,
+                            &setup_moves,
+                            new_block,
+                            new_meta,
+                        ) {
                             results.push(new_segment);
                         }
                     }
@@ -152,18 +423,9 @@ impl Solver {
             println!("  WARNING: NO OPTIONS. You may need to increase `MAX_BLOCKS`");
         }
 
-        self.segments.add_segments(step, new_segments);
+        self.add_segments(step, new_segments);
 
-        // Blockbuild
-        for target in (target_block_count..init_blocks).rev() {
-            self.do_blockbuilding_step(target);
-            // if self.steps.last().unwrap().is_empty() {
-            //     log!(self.params, 1, "No solutions! Giving up ...");
-            //     std::process::exit(1);
-            // }
-        }
-
-        println!("  Completed stage in {:?}", t.elapsed());
+        init_blocks
     }
 
     fn do_blockbuilding_step(&mut self, block_target: usize) {
@@ -172,47 +434,88 @@ impl Solver {
         let mut max_depth = 0;
 
         let new_segments = self.do_step(|this, prev_segments| {
-            let mut new_segments = vec![];
-            for depth in 0..=this.params.max_depth {
-                let desired_solution_count = match depth {
-                    ..=1 => crate::MIN_SOLUTION_COUNT_DEPTH_1,
-                    2 => crate::MIN_SOLUTION_COUNT_DEPTH_2,
-                    3 => crate::MIN_SOLUTION_COUNT_DEPTH_3,
-                    4.. => crate::MIN_SOLUTION_COUNT_DEPTH_4,
-                };
-                let solutions_left_to_find =
-                    desired_solution_count.saturating_sub(new_segments.len());
-                overprint!("  Blockbuilding to {block_target} at depth {depth} ...");
-
-                new_segments.par_extend(
-                    prev_segments
-                        .par_iter()
-                        .flat_map(|&prev_segment| {
+            let description = format!("Blockbuilding to {block_target}");
+            let (new_segments, depth) = this.iddfs(
+                prev_segments,
+                &description,
+                |prev_segments, depth, limit| {
+                    this.blockbuild_shards(prev_segments, block_target, depth, limit)
+                },
+            );
+            max_depth = depth;
+            new_segments
+        });
+
+        let min_twist_count = new_segments
+            .iter()
+            .map(|s| s.total_twist_count)
+            .min()
+            .unwrap_or(0);
+        overprintln!(
+            "  Blockbuilt to {block_target} with max depth {max_depth} ({} solutions; best is {} {})",
+            new_segments.len(),
+            min_twist_count,
+            self.params.metric,
+        );
+
+        self.add_segments(step, new_segments);
+    }
+
+    /// Runs bidirectional search to solve the last step to a single block, and
+    /// returns whether any solutions were found.
+    fn do_bidirectional_step(&mut self) -> bool {
+        let step = self.segments.next_step();
+
+        let Some(&first_segment) = self.segments.segment_ids_for_step(step - 1).first() else {
+            return false;
+        };
+        let goal = self.segments[first_segment].meta.goal_block();
+        let backward_depth = self.params.bidirectional_depth;
+        let t = std::time::Instant::now();
+        let table = BackwardTable::new(self.puzzle, goal, backward_depth);
+        log!(
+            self.params,
+            2,
+            "Built backward table to depth {backward_depth} with {} states in {:?}",
+            table.len(),
+            t.elapsed(),
+        );
+
+        let mut max_depth = 0;
+
+        let new_segments = self.do_step(|this, prev_segments| {
+            let description = format!("Bidirectional search with backward depth {backward_depth}");
+            let (new_segments, depth) = this.iddfs(
+                prev_segments,
+                &description,
+                |prev_segments, depth, limit| {
+                    search_each(
+                        this.params.deterministic,
+                        prev_segments,
+                        limit,
+                        |&prev_segment| {
+                            let segment = this.segments[prev_segment].next_step(prev_segment);
+                            let Some(to_table_frame) =
+                                table.rotation_from(this.puzzle, segment.meta.goal_block())
+                            else {
+                                return vec![];
+                            };
                             let mut results = vec![];
-                            dfs_blockbuild(
+                            dfs_bidirectional(
                                 this.params,
                                 this.puzzle,
-                                block_target,
+                                &table,
+                                to_table_frame,
                                 depth,
                                 &mut results,
-                                this.segments[prev_segment].next_step(prev_segment),
-                                None,
-                                if depth > this.params.parallel_depth {
-                                    this.params.parallel_depth
-                                } else {
-                                    0
-                                },
+                                segment,
                             );
                             results
-                        })
-                        .take_any(solutions_left_to_find),
-                );
-
-                max_depth = depth;
-                if new_segments.len() >= desired_solution_count {
-                    break;
-                }
-            }
+                        },
+                    )
+                },
+            );
+            max_depth = depth;
             new_segments
         });
 
@@ -222,12 +525,58 @@ impl Solver {
             .min()
             .unwrap_or(0);
         overprintln!(
-            "  Blockbuilt to {block_target} with max depth {max_depth} ({} solutions; best is {} ETM)",
+            "  Bidirectional search with max depth {max_depth}+{backward_depth} ({} solutions; best is {} {})",
             new_segments.len(),
             min_twist_count,
+            self.params.metric,
         );
 
+        if new_segments.is_empty() {
+            return false;
+        }
+        self.add_segments(step, new_segments);
+        true
+    }
+
+    /// Adds segments for a new step and frees segments that are no longer
+    /// needed.
+    fn add_segments(&mut self, step: usize, new_segments: Vec<Segment>) {
         self.segments.add_segments(step, new_segments);
+        let removed_count = self.segments.collect_garbage();
+        log!(self.params, 3, "Freed {removed_count} unreachable segments");
+    }
+
+    /// Runs `search(prev_segments, depth, limit)` at increasing depths until
+    /// enough solutions are found or the maximum depth is reached. `search`
+    /// should return at most `limit` solutions.
+    ///
+    /// Returns the solutions and the maximum depth searched.
+    fn iddfs(
+        &self,
+        prev_segments: &[SegmentId],
+        description: &str,
+        search: impl Fn(&[SegmentId], usize, usize) -> Vec<Segment>,
+    ) -> (Vec<Segment>, usize) {
+        let mut new_segments = vec![];
+        let mut max_depth = 0;
+        for depth in 0..=self.params.max_depth {
+            let desired_solution_count = match depth {
+                ..=1 => crate::MIN_SOLUTION_COUNT_DEPTH_1,
+                2 => crate::MIN_SOLUTION_COUNT_DEPTH_2,
+                3 => crate::MIN_SOLUTION_COUNT_DEPTH_3,
+                4.. => crate::MIN_SOLUTION_COUNT_DEPTH_4,
+            };
+            let solutions_left_to_find = desired_solution_count.saturating_sub(new_segments.len());
+            overprint!("  {description} at depth {depth} ...");
+
+            new_segments.extend(search(prev_segments, depth, solutions_left_to_find));
+
+            max_depth = depth;
+            if new_segments.len() >= desired_solution_count {
+                break;
+            }
+        }
+        (new_segments, max_depth)
     }
 
     #[must_use]
@@ -281,6 +630,64 @@ impl Solver {
     }
 }
 
+/// Runs `search` from each of `starts` in parallel and returns at most `limit`
+/// of the results.
+///
+/// If `deterministic` is `true`, the results are the first `limit` in the
+/// order of `starts`. Otherwise they are whichever are found first.
+fn search_each<T: Sync>(
+    deterministic: bool,
+    starts: &[T],
+    limit: usize,
+    search: impl Sync + Send + Fn(&T) -> Vec<Segment>,
+) -> Vec<Segment> {
+    if !deterministic {
+        return starts.par_iter().flat_map(search).take_any(limit).collect();
+    }
+
+    // Search a few starts per thread at a time, and stop once the earlier
+    // ones have found enough solutions. The chunk size doesn't affect the
+    // results.
+    let chunk_size = rayon::current_num_threads() * 4;
+    let mut results = vec![];
+    for chunk in starts.chunks(chunk_size) {
+        if results.len() >= limit {
+            break;
+        }
+        // `collect()` preserves order.
+        results.par_extend(chunk.par_iter().flat_map(&search));
+    }
+    results.truncate(limit);
+    results
+}
+
+/// Runs [`dfs_blockbuild()`] to `depth` from the start of a new step.
+fn blockbuild_from(
+    params: BlockBuildingSearchParams,
+    puzzle: &Puzzle,
+    block_target: usize,
+    depth: usize,
+    segment: Segment,
+) -> Vec<Segment> {
+    let parallel_depth = params.effective_parallel_depth();
+    let mut results = vec![];
+    dfs_blockbuild(
+        params,
+        puzzle,
+        block_target,
+        depth,
+        &mut results,
+        segment,
+        None,
+        if depth > parallel_depth {
+            parallel_depth
+        } else {
+            0
+        },
+    );
+    results
+}
+
 /// Runs a depth-first search to `remaining_depth` for sequences of moves that
 /// results in at most `expected_blocks` blocks.
 ///
@@ -325,31 +732,23 @@ pub fn dfs_blockbuild(
         return; // probably not solvable; give up
     }
 
-    let combined_layer_mask = state
-        .blocks
-        .iter()
-        .map(|b| b.layers())
-        .fold(PackedLayers::EMPTY, |a, b| a | b);
+    if let Some(db) = params.pattern_database
+        && !puzzle.slice_twists
+        && db.min_block_count(state, remaining_depth) > expected_blocks
+    {
+        return; // definitely not solvable; give up
+    }
 
-    let mut last_grips = segment_twists.iter().rev().map(|twist| twist.grip);
-    let last_grip = last_grips.next();
-    let second_to_last_grip = last_grips.next();
-    let grip_is_worth_testing = |grip: &&GripData| {
-        if last_grip == Some(grip.id) {
-            return false; // same grip as last move
-        }
-        if last_grip == Some(grip.id.opposite()) && second_to_last_grip == Some(grip.id) {
-            return false; // opposite grip already moved
-        }
-        if combined_layer_mask.grip_status(grip.id) == GripStatus::Inactive {
-            return false; // doesn't move any block
-        }
-        // TODO: don't check opposite if it was 2nd-to-last move
-        true
-    };
+    let last_twist = segment_twists.last().copied();
+    let grips_worth_testing = grips_worth_testing(puzzle, state, &segment_twists);
+    let grip_is_worth_testing = |grip: &&GripData| grips_worth_testing.contains(grip.id);
+    let twist_is_worth_testing =
+        |twist: &Twist| is_twist_worth_testing(puzzle, state, last_twist, *twist);
 
     let explore_twist = |twist, solutions_buffer: &mut Vec<Segment>| {
-        if let Some(new_partial_solution) = solution_so_far.push_twist(twist, last_grip) {
+        if let Some(new_partial_solution) =
+            solution_so_far.push_twist(puzzle, params.metric, twist, last_twist)
+        {
             dfs_blockbuild(
                 params,
                 puzzle,
@@ -365,7 +764,9 @@ pub fn dfs_blockbuild(
 
     if remaining_parallel_depth > 0 {
         let grips = puzzle.grips.par_iter().filter(grip_is_worth_testing);
-        let twists = grips.flat_map(|grip| grip.par_twists());
+        let twists = grips
+            .flat_map(|grip| grip.par_twists())
+            .filter(twist_is_worth_testing);
         solutions_buffer.par_extend(twists.flat_map_iter(|twist| {
             let mut solutions_buffer = vec![];
             explore_twist(twist, &mut solutions_buffer);
@@ -373,7 +774,141 @@ pub fn dfs_blockbuild(
         }));
     } else {
         let grips = puzzle.grips.iter().filter(grip_is_worth_testing);
-        let twists = grips.flat_map(|grip| grip.twists());
+        let twists = grips
+            .flat_map(|grip| grip.twists())
+            .filter(twist_is_worth_testing);
         twists.for_each(|twist| explore_twist(twist, solutions_buffer));
     }
 }
+
+/// Returns the set of grips that are worth twisting in `state` after
+/// `segment_twists`.
+fn grips_worth_testing(puzzle: &Puzzle, state: BlockSet, segment_twists: &[Twist]) -> GripSet {
+    if !puzzle.has_only_outer_twists() {
+        // The twist sequence automaton only knows about outer layers, so
+        // slice twists, wide twists, and rotations are checked by
+        // `is_twist_worth_testing()` instead.
+        return puzzle.grip_set();
+    }
+
+    // Segments that didn't come from a search might not be canonical, in which
+    // case we can't rule out any grips based on previous twists.
+    let seq_state = TwistSeqState::after(segment_twists.iter().map(|twist| twist.grip()))
+        .unwrap_or(TwistSeqState::START);
+    seq_state.allowed_grips() & moved_grips(state)
+}
+
+/// Returns whether `twist` is worth testing in `state` after `last_twist`, for
+/// puzzles with slice twists or rotations. This doesn't rule out any twists on
+/// other puzzles; see [`grips_worth_testing()`].
+fn is_twist_worth_testing(
+    puzzle: &Puzzle,
+    state: BlockSet,
+    last_twist: Option<Twist>,
+    twist: Twist,
+) -> bool {
+    if puzzle.has_only_outer_twists() {
+        return true;
+    }
+    // Consecutive rotations are never tried. Most pairs could be combined into
+    // one, and the rest give rotations that fix no grip, which aren't moves.
+    let can_combine_with_last = last_twist.is_some_and(|last| {
+        (last.grip(), last.layers()) == (twist.grip(), twist.layers())
+            || (last.is_rotation() && twist.is_rotation())
+    });
+    !can_combine_with_last
+        && state
+            .combined_layers()
+            .is_moved_by(twist.grip(), twist.layers())
+}
+
+/// Returns the set of grips that move at least one block in `state`.
+fn moved_grips(state: BlockSet) -> GripSet {
+    let combined_layer_mask = state.combined_layers();
+    HYPERCUBE_GRIPS
+        .into_iter()
+        .filter(|&grip| combined_layer_mask.grip_status(grip) != GripStatus::Inactive)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the segments that reduce `state` to at most `expected_blocks`
+    /// blocks within `depth` moves, using [`Heuristic::Fast`].
+    fn search(
+        puzzle: &Puzzle,
+        state: BlockSet,
+        expected_blocks: usize,
+        depth: usize,
+    ) -> Vec<Segment> {
+        let params = BlockBuildingSearchParams {
+            heuristic: Heuristic::Fast,
+            ..Default::default()
+        };
+        let mut results = vec![];
+        let init = Segment {
+            state,
+            ..Default::default()
+        };
+        dfs_blockbuild(
+            params,
+            puzzle,
+            expected_blocks,
+            depth,
+            &mut results,
+            init,
+            None,
+            0,
+        );
+        results
+    }
+
+    fn f2l_block_state() -> BlockSet {
+        let f2l_block = Block::new_solved([], [O]).unwrap();
+        BlockSet {
+            blocks: crate::StackVec::from_iter([f2l_block]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_slice_twist_search() {
+        let puzzle = &*RUBIKS_4D_WITH_SLICES;
+        let slice = TWISTS_FROM_NAME["RU"].with_layers(TwistLayers::MIDDLE);
+        let wide = TWISTS_FROM_NAME["UF"].with_layers(TwistLayers::WIDE);
+        let state = (f2l_block_state().do_twist(slice, puzzle))
+            .and_then(|s| s.do_twist(wide, puzzle))
+            .unwrap();
+
+        let results = search(puzzle, state, 1, 2);
+        let undo = vec![wide.inv(), slice.inv()];
+        assert!(results.iter().any(|s| s.segment_twists.to_vec() == undo));
+    }
+
+    #[test]
+    fn test_rotation_search() {
+        let puzzle = &*RUBIKS_4D_WITH_ROTATIONS;
+        let rotation = TWISTS_FROM_NAME["RU"].with_layers(TwistLayers::ALL);
+        let twist = TWISTS_FROM_NAME["UF"];
+        let state = (f2l_block_state().do_twist(twist, puzzle))
+            .and_then(|s| s.do_twist(rotation, puzzle))
+            .unwrap();
+
+        let results = search(puzzle, state, 1, 2);
+        // The rotation takes up search depth but costs nothing.
+        let undo = vec![rotation.inv(), twist.inv()];
+        let solution = results.iter().find(|s| s.segment_twists.to_vec() == undo);
+        assert_eq!(solution.unwrap().total_twist_count, 1);
+        assert!(
+            results
+                .iter()
+                .any(|s| s.segment_twists.iter().any(|t| t.is_rotation()))
+        );
+
+        let best_cost = |results: &[Segment]| results.iter().map(|s| s.total_twist_count).min();
+        let without_rotations = search(&RUBIKS_4D, state, 1, 2);
+        assert!(best_cost(&results) <= best_cost(&without_rotations));
+        assert!(best_cost(&without_rotations).is_some());
+    }
+}
@@ -1,4 +1,4 @@
-use super::Heuristic;
+use super::{Heuristic, Metric, PatternDatabase};
 
 /// Search parameters for a [`BlockBuildingSearch`];
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -6,11 +6,31 @@ pub struct BlockBuildingSearchParams {
     /// Heuristic to use for pruning branches.
     pub heuristic: Heuristic,
 
+    /// Metric to minimize.
+    pub metric: Metric,
+
     /// Maximum depth for IDDFS.
     pub max_depth: usize,
 
-    /// Maximum depth to parallelize.
-    pub parallel_depth: usize,
+    /// Maximum depth to parallelize, or `None` to choose it based on the number
+    /// of threads in the current rayon thread pool. See
+    /// [`Self::effective_parallel_depth()`].
+    pub parallel_depth: Option<usize>,
+
+    /// Pattern database to use for pruning branches, in addition to
+    /// [`Self::heuristic`].
+    pub pattern_database: Option<&'static PatternDatabase>,
+
+    /// Number of twists to search backward from the goal in the final stage,
+    /// using meet-in-the-middle search. If this is zero, the final stage uses
+    /// IDDFS like every other stage.
+    pub bidirectional_depth: usize,
+
+    /// Whether to keep the same solutions regardless of the order in which
+    /// parallel searches finish, so that the same scramble and parameters
+    /// always give the same solution. This is a bit slower because each search
+    /// must wait for the ones before it when it has found enough solutions.
+    pub deterministic: bool,
 
     /// How much to print.
     pub verbosity: u8,
@@ -20,9 +40,51 @@ impl Default for BlockBuildingSearchParams {
     fn default() -> Self {
         Self {
             heuristic: Heuristic::Fast,
+            metric: Metric::Stm,
             max_depth: 3,
-            parallel_depth: 2,
+            parallel_depth: None,
+            pattern_database: None,
+            bidirectional_depth: 0,
+            deterministic: false,
             verbosity: 1,
         }
     }
 }
+
+impl BlockBuildingSearchParams {
+    /// Returns [`Self::parallel_depth`] if it is set. Otherwise returns a
+    /// depth based on the number of threads in the current rayon thread pool,
+    /// since splitting work more finely than there are threads to run it only
+    /// adds overhead.
+    pub fn effective_parallel_depth(self) -> usize {
+        self.parallel_depth
+            .unwrap_or_else(|| match rayon::current_num_threads() {
+                ..=1 => 0,
+                2..=8 => 1,
+                9.. => 2,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_parallel_depth() {
+        let params = BlockBuildingSearchParams::default();
+        for (thread_count, expected) in [(1, 0), (4, 1), (16, 2)] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(thread_count)
+                .build()
+                .unwrap();
+            assert_eq!(pool.install(|| params.effective_parallel_depth()), expected);
+        }
+
+        let params = BlockBuildingSearchParams {
+            parallel_depth: Some(3),
+            ..params
+        };
+        assert_eq!(params.effective_parallel_depth(), 3);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::time::Duration;
+use std::{fmt, io};
+
+use itertools::Itertools;
+
+use crate::sim::*;
+
+const MAGIC_STRING: &[u8] = b"robodoan-pdb-v1\n";
+
+/// Value in a distance table for states that cannot be paired.
+const UNREACHABLE: u8 = u8::MAX;
+
+/// Class, rotation into the canonical frame, and whether the pieces are
+/// swapped, for each pair of adjacent pieces.
+type PiecePairClasses = HashMap<(GripSet, GripSet), (usize, ElemId, bool)>;
+
+/// 4D pattern database, built in memory on first use. To cache it on disk
+/// instead, use [`PatternDatabase::load_or_build()`].
+#[static_init::dynamic(lazy)]
+pub static PATTERN_DATABASE_4D: PatternDatabase = PatternDatabase::build(&RUBIKS_4D);
+
+/// Where [`PatternDatabase::load_or_build()`] got the pattern database.
+#[derive(Debug)]
+pub enum PatternDatabaseSource {
+    /// Loaded from the file.
+    Loaded,
+    /// Built and saved to the file.
+    Built(Duration),
+    /// Built, but saving it to the file failed.
+    Unsaved(Duration, io::Error),
+}
+impl fmt::Display for PatternDatabaseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Loaded => write!(f, "loaded"),
+            Self::Built(elapsed) => write!(f, "built in {elapsed:?} and saved"),
+            Self::Unsaved(elapsed, e) => {
+                write!(f, "built in {elapsed:?}, but unable to save it: {e}")
+            }
+        }
+    }
+}
+
+/// Precomputed table of the exact number of twists needed to pair two adjacent
+/// pieces, for every attitude of each piece.
+///
+/// Two adjacent pieces in the same block must be paired, and each twist moves
+/// the pieces in a block the same way, so these distances are admissible lower
+/// bounds for blockbuilding.
+///
+/// Pairs of pieces are grouped into classes up to rotation of the whole puzzle
+/// so that only one table is needed for each class.
+#[derive(PartialEq, Eq)]
+pub struct PatternDatabase {
+    /// Canonical `(outer, inner)` piece pair for each class.
+    class_representatives: Vec<(GripSet, GripSet)>,
+    /// Distance table for each class, indexed by the attitudes of the two
+    /// pieces in the canonical frame.
+    tables: Vec<Box<[u8]>>,
+    /// Class of each pair of adjacent pieces.
+    classes: PiecePairClasses,
+}
+impl fmt::Debug for PatternDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PatternDatabase")
+            .field("class_representatives", &self.class_representatives)
+            .finish_non_exhaustive()
+    }
+}
+impl PatternDatabase {
+    /// Loads the pattern database from `path`, or builds it and saves it to
+    /// `path` if it does not exist or is out of date.
+    pub fn load_or_build(puzzle: &Puzzle, path: impl AsRef<Path>) -> (Self, PatternDatabaseSource) {
+        let path = path.as_ref();
+        if let Ok(bytes) = std::fs::read(path)
+            && let Some(db) = Self::from_bytes(puzzle, &bytes)
+        {
+            return (db, PatternDatabaseSource::Loaded);
+        }
+
+        let t = std::time::Instant::now();
+        let db = Self::build(puzzle);
+        let elapsed = t.elapsed();
+        let source = match std::fs::write(path, db.to_bytes()) {
+            Ok(()) => PatternDatabaseSource::Built(elapsed),
+            Err(e) => PatternDatabaseSource::Unsaved(elapsed, e),
+        };
+        (db, source)
+    }
+
+    /// Builds the pattern database using breadth-first search from every
+    /// paired state.
+    pub fn build(puzzle: &Puzzle) -> Self {
+        let (class_representatives, classes) = Self::classify_piece_pairs(puzzle);
+        let tables = class_representatives
+            .iter()
+            .map(|&(outer, inner)| Self::build_table(puzzle, outer, inner))
+            .collect();
+        Self {
+            class_representatives,
+            tables,
+            classes,
+        }
+    }
+
+    fn classify_piece_pairs(puzzle: &Puzzle) -> (Vec<(GripSet, GripSet)>, PiecePairClasses) {
+        let axes = puzzle
+            .grips
+            .iter()
+            .map(|g| g.id.axis())
+            .unique()
+            .collect_vec();
+        let positions = axes
+            .iter()
+            .map(|&axis| {
+                let [pos, neg] = GripId::pair_on_axis(axis);
+                [
+                    GripSet::NONE,
+                    GripSet::from_iter([pos]),
+                    GripSet::from_iter([neg]),
+                ]
+            })
+            .multi_cartesian_product()
+            .map(|grips| grips.into_iter().fold(GripSet::NONE, |a, b| a | b));
+        let adjacent_pairs =
+            positions.flat_map(|outer| outer.iter().map(move |g| (outer, outer - g)));
+
+        let mut class_representatives = vec![];
+        let mut classes = HashMap::new();
+        for (outer, inner) in adjacent_pairs {
+            let (rep, rot) = puzzle
+                .rotations
+                .iter()
+                .map(|&rot| ((rot * outer).0, (rot * inner).0, rot))
+                .min_by_key(|&(a, b, _)| (a, b))
+                .map(|(a, b, rot)| ((GripSet(a), GripSet(b)), rot))
+                .unwrap();
+            let class = match class_representatives.iter().position(|&r| r == rep) {
+                Some(i) => i,
+                None => {
+                    class_representatives.push(rep);
+                    class_representatives.len() - 1
+                }
+            };
+            classes.insert((outer, inner), (class, rot, false));
+            classes.insert((inner, outer), (class, rot, true));
+        }
+        (class_representatives, classes)
+    }
+
+    fn build_table(puzzle: &Puzzle, outer: GripSet, inner: GripSet) -> Box<[u8]> {
+        let outer_piece = Piece::new_solved(outer.iter());
+        let inner_piece = Piece::new_solved(inner.iter());
+        let is_paired = |a1: ElemId, a2: ElemId| {
+            Block::from(a1 * outer_piece)
+                .try_merge(Block::from(a2 * inner_piece), puzzle)
+                .is_some()
+        };
+
+        let mut table = vec![UNREACHABLE; ELEM_COUNT * ELEM_COUNT].into_boxed_slice();
+        let mut queue = VecDeque::new();
+        for (&a1, &a2) in itertools::iproduct!(&puzzle.rotations, &puzzle.rotations) {
+            if is_paired(a1, a2) {
+                table[table_index(a1, a2)] = 0;
+                queue.push_back((a1, a2));
+            }
+        }
+
+        // Attitude of each piece after each twist, indexed by attitude.
+        let twisted_attitudes = |piece: Piece| -> Vec<Vec<ElemId>> {
+            (0..ELEM_COUNT as u8)
+                .map(|id| {
+                    let piece = ElemId::new(id) * piece;
+                    puzzle
+                        .twists
+                        .iter()
+                        .map(|&t| (t * piece).attitude)
+                        .collect()
+                })
+                .collect()
+        };
+        let outer_twisted = twisted_attitudes(outer_piece);
+        let inner_twisted = twisted_attitudes(inner_piece);
+
+        // Every twist's inverse is also a twist, so searching outward from the
+        // paired states gives the distance to the nearest paired state.
+        while let Some((a1, a2)) = queue.pop_front() {
+            let next_distance = table[table_index(a1, a2)] + 1;
+            let outer_next = &outer_twisted[a1.id() as usize];
+            let inner_next = &inner_twisted[a2.id() as usize];
+            for (&new_a1, &new_a2) in std::iter::zip(outer_next, inner_next) {
+                let entry = &mut table[table_index(new_a1, new_a2)];
+                if *entry == UNREACHABLE {
+                    *entry = next_distance;
+                    queue.push_back((new_a1, new_a2));
+                }
+            }
+        }
+
+        table
+    }
+
+    /// Returns the minimum number of twists needed to pair two adjacent pieces
+    /// with the given solved positions and current attitudes, or `None` if the
+    /// pieces are not adjacent.
+    pub fn pair_distance(
+        &self,
+        (piece1, attitude1): (GripSet, ElemId),
+        (piece2, attitude2): (GripSet, ElemId),
+    ) -> Option<u8> {
+        let &(class, rot, swapped) = self.classes.get(&(piece1, piece2))?;
+        let [a1, a2] = [attitude1, attitude2].map(|a| rot.transform(a));
+        let (outer_attitude, inner_attitude) = if swapped { (a2, a1) } else { (a1, a2) };
+        Some(self.tables[class][table_index(outer_attitude, inner_attitude)])
+    }
+
+    /// Returns a lower bound on the number of blocks that `state` can be
+    /// reduced to within `remaining_moves`.
+    ///
+    /// A block that cannot pair any of its pieces with an adjacent piece from
+    /// another block within `remaining_moves` is stuck on its own.
+    pub fn min_block_count(&self, state: BlockSet, remaining_moves: usize) -> usize {
+        let blocks_when_solved = state.blocks.map(|b| b.at_solved().layers());
+        let can_pair = |i: usize, j: usize| {
+            let (b1, b2) = (state.blocks[i], state.blocks[j]);
+            blocks_when_solved[i]
+                .adjacent_pieces(blocks_when_solved[j])
+                .any(|(p1, p2)| {
+                    self.pair_distance((p1, b1.attitude()), (p2, b2.attitude()))
+                        .is_some_and(|d| (d as usize) <= remaining_moves)
+                })
+        };
+
+        let block_count = state.blocks.len();
+        let stuck_blocks = (0..block_count)
+            .filter(|&i| !(0..block_count).any(|j| i != j && can_pair(i, j)))
+            .count();
+        stuck_blocks + (stuck_blocks < block_count) as usize
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC_STRING.to_vec();
+        bytes.push(self.class_representatives.len() as u8);
+        for (&(outer, inner), table) in std::iter::zip(&self.class_representatives, &self.tables) {
+            bytes.extend([outer.0, inner.0]);
+            bytes.extend_from_slice(table);
+        }
+        bytes
+    }
+
+    fn from_bytes(puzzle: &Puzzle, bytes: &[u8]) -> Option<Self> {
+        let (class_representatives, classes) = Self::classify_piece_pairs(puzzle);
+
+        let mut bytes = bytes.strip_prefix(MAGIC_STRING)?;
+        let (&class_count, rest) = bytes.split_first()?;
+        bytes = rest;
+        if class_count as usize != class_representatives.len() {
+            return None;
+        }
+        let mut tables = vec![];
+        for &(outer, inner) in &class_representatives {
+            let (header, rest) = bytes.split_at_checked(2)?;
+            if header != [outer.0, inner.0] {
+                return None;
+            }
+            let (table, rest) = rest.split_at_checked(ELEM_COUNT * ELEM_COUNT)?;
+            tables.push(table.into());
+            bytes = rest;
+        }
+        bytes.is_empty().then_some(Self {
+            class_representatives,
+            tables,
+            classes,
+        })
+    }
+}
+
+fn table_index(a1: ElemId, a2: ElemId) -> usize {
+    a1.id() as usize * ELEM_COUNT + a2.id() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_database() {
+        let db = PatternDatabase::build(&RUBIKS_4D);
+
+        // core-center, center-ridge, ridge-edge, edge-corner
+        assert_eq!(4, db.class_representatives.len());
+
+        let path = std::env::temp_dir().join(format!("robodoan-test-{}.pdb", std::process::id()));
+        let (built, source) = PatternDatabase::load_or_build(&RUBIKS_4D, &path);
+        assert!(
+            matches!(source, PatternDatabaseSource::Built(_)),
+            "{source}"
+        );
+        assert!(db == built);
+        let (loaded, source) = PatternDatabase::load_or_build(&RUBIKS_4D, &path);
+        assert!(matches!(source, PatternDatabaseSource::Loaded), "{source}");
+        assert!(db == loaded);
+        std::fs::remove_file(path).unwrap();
+
+        let corner = GripSet::from_iter([R, U, F, O]);
+        let edge = GripSet::from_iter([R, U, F]);
+        let ridge = GripSet::from_iter([U, F]);
+        for (p1, p2) in [(corner, edge), (edge, ridge), (ridge, edge)] {
+            assert_eq!(Some(0), db.pair_distance((p1, IDENT), (p2, IDENT)));
+        }
+        assert_eq!(None, db.pair_distance((corner, IDENT), (ridge, IDENT)));
+
+        // Twisting a single piece away takes one twist to undo.
+        let twists = &RUBIKS_4D.twists;
+        let find_twist = |f: fn(Twist) -> bool| *twists.iter().find(|&&t| f(t)).unwrap();
+        let twist = find_twist(|t| t.grip() == O);
+        let edge_twist = find_twist(|t| t.grip() == R);
+        let d = |a1, a2| db.pair_distance((corner, a1), (edge, a2)).unwrap();
+        assert_eq!(1, d(twist.transform, IDENT));
+        assert_eq!(0, d(edge_twist.transform, edge_twist.transform));
+        assert!(d(twist.transform, edge_twist.transform) <= 2);
+    }
+}
@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+
+use super::SolutionMetadata;
+use crate::sim::*;
+
+/// Comparison used to rank candidate solutions, from best to worst.
+pub(super) type Ranking = dyn Fn(&Candidate, &Candidate) -> Ordering + Send + Sync;
+
+/// Complete solution found by the solver, which is ranked against the others
+/// at the end of the solve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub twists: Vec<Twist>,
+    /// State of the puzzle after the scramble and [`Self::twists`].
+    pub state: PuzzleState,
+    pub meta: SolutionMetadata,
+    /// Length of [`Self::twists`] in the solver's [`super::Metric`].
+    pub length: usize,
+    /// Number of ridges, edges, and corners in the last layer that are not
+    /// oriented. See [`PuzzleState::unoriented_pieces()`].
+    pub unoriented_pieces: [usize; 3],
+}
+impl Candidate {
+    pub fn new(
+        twists: Vec<Twist>,
+        state: PuzzleState,
+        meta: SolutionMetadata,
+        length: usize,
+    ) -> Self {
+        // Rotations move the last layer along with the rest of the puzzle.
+        let net_rotation = twists
+            .iter()
+            .filter(|t| t.is_rotation())
+            .fold(IDENT, |acc, t| t.transform * acc);
+        let unoriented_pieces = state.unoriented_pieces(net_rotation * meta.last_layer());
+        Self {
+            twists,
+            state,
+            meta,
+            length,
+            unoriented_pieces,
+        }
+    }
+
+    /// Compares by length, then by unoriented pieces. This is the default
+    /// ranking.
+    pub fn cmp_by_length(&self, other: &Self) -> Ordering {
+        (self.length, self.unoriented_pieces, &self.twists).cmp(&(
+            other.length,
+            other.unoriented_pieces,
+            &other.twists,
+        ))
+    }
+
+    /// Returns whether `self` is no worse than `other` in length and in each
+    /// count of unoriented pieces, and better in at least one of them.
+    pub fn dominates(&self, other: &Self) -> bool {
+        let a = std::iter::once(self.length).chain(self.unoriented_pieces);
+        let b = std::iter::once(other.length).chain(other.unoriented_pieces);
+        let mut better = false;
+        for (a, b) in std::iter::zip(a, b) {
+            match a.cmp(&b) {
+                Ordering::Less => better = true,
+                Ordering::Equal => (),
+                Ordering::Greater => return false,
+            }
+        }
+        better
+    }
+}
+
+/// Returns the candidates that are not dominated by any other candidate, in
+/// their original order. See [`Candidate::dominates()`].
+pub fn pareto_front(candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let dominated = (candidates.iter())
+        .map(|c| candidates.iter().any(|other| other.dominates(c)))
+        .collect::<Vec<_>>();
+    std::iter::zip(candidates, dominated)
+        .filter(|&(_, dominated)| !dominated)
+        .map(|(c, _)| c)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pareto_front() {
+        let candidate = |length, unoriented_pieces| Candidate {
+            twists: vec![],
+            state: PuzzleState::default(),
+            meta: SolutionMetadata::default(),
+            length,
+            unoriented_pieces,
+        };
+        let candidates = vec![
+            candidate(40, [2, 4, 6]),
+            candidate(41, [0, 4, 6]),
+            candidate(41, [2, 4, 6]), // dominated by the first
+            candidate(42, [0, 0, 0]),
+            candidate(42, [0, 0, 0]), // equal, so not dominated
+            candidate(43, [0, 2, 0]), // dominated by the fourth
+        ];
+        let front = pareto_front(candidates.clone());
+        assert_eq!(front, [0, 1, 3, 4].map(|i| candidates[i].clone()).to_vec(),);
+    }
+}
@@ -1,7 +1,8 @@
 use std::fmt;
 use std::ops::Index;
 
-use super::SolutionMetadata;
+use super::checkpoint::Encode;
+use super::{Metric, SolutionMetadata};
 use crate::StackVec;
 use crate::sim::*;
 
@@ -11,6 +12,7 @@ pub struct Segment {
     pub state: BlockSet, // 64 bytes
     pub segment_twists: StackVec<Twist, { crate::MAX_SOLUTION_SEGMENT_LEN }>, // 23 bytes
     pub previous_segment: SegmentId, // 16 bytes
+    /// Length of the whole solution so far, in the solver's [`Metric`].
     pub total_twist_count: usize, // 8 bytes
     pub meta: SolutionMetadata, // 20 bytes
 }
@@ -18,7 +20,7 @@ impl fmt::Display for Segment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let block_count = self.state.blocks.len();
         let twist_count = self.total_twist_count;
-        write!(f, "{block_count} blocks in {twist_count} ETM")
+        write!(f, "{block_count} blocks in {twist_count} moves")
     }
 }
 impl Segment {
@@ -29,26 +31,38 @@ impl Segment {
     const SIZE_ASSERT: [u8; 128] = [0; std::mem::size_of::<Self>()];
 
     #[must_use]
-    pub fn push_twist(&self, twist: Twist, last_grip: Option<GripId>) -> Option<Self> {
+    pub fn push_twist(
+        &self,
+        puzzle: &Puzzle,
+        metric: Metric,
+        twist: Twist,
+        last_twist: Option<Twist>,
+    ) -> Option<Self> {
         Some(Self {
-            state: self.state.do_twist(twist, 4)?, // 4D
+            state: self.state.do_twist(twist, puzzle)?,
             segment_twists: self.segment_twists.push(twist)?,
             previous_segment: self.previous_segment,
-            total_twist_count: self.total_twist_count + (last_grip != Some(twist.grip)) as usize,
+            total_twist_count: self.total_twist_count + metric.twist_cost(twist, last_twist),
             meta: self.meta,
         })
     }
+    /// Adds the pieces in `new_block` where they are after doing `setup_moves`
+    /// on `initial_state`. See [`BlockSet::add_block_from_state()`].
     pub fn push_block(
         &self,
         puzzle: &Puzzle,
+        initial_state: &PuzzleState,
         setup_moves: &[Twist],
         new_block: Block,
         new_meta: SolutionMetadata,
     ) -> Option<Self> {
         Some(Self {
-            state: self
-                .state
-                .add_block_with_setup_moves(puzzle, setup_moves, new_block)?,
+            state: self.state.add_block_from_state(
+                puzzle,
+                initial_state,
+                setup_moves,
+                new_block,
+            )?,
             meta: new_meta,
             ..self.clone()
         })
@@ -84,6 +98,25 @@ impl Segment {
     }
 }
 
+impl Encode for Segment {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.state.encode(out);
+        self.segment_twists.encode(out);
+        self.previous_segment.encode(out);
+        self.total_twist_count.encode(out);
+        self.meta.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            state: BlockSet::decode(input)?,
+            segment_twists: StackVec::decode(input)?,
+            previous_segment: SegmentId::decode(input)?,
+            total_twist_count: usize::decode(input)?,
+            meta: SolutionMetadata::decode(input)?,
+        })
+    }
+}
+
 /// ID for a [`Segment`].
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SegmentId(usize);
@@ -91,8 +124,20 @@ impl SegmentId {
     const INIT: Self = Self(0);
 }
 
+impl Encode for SegmentId {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        usize::decode(input).map(Self)
+    }
+}
+
 pub struct SegmentStore {
     pub scramble: Vec<Twist>,
+    /// State of the puzzle before the scramble, which is solved unless the
+    /// solve started from a [`PuzzleState`].
+    pub initial_state: PuzzleState,
 
     segments: Vec<Segment>,
     steps: Vec<Vec<SegmentId>>,
@@ -104,14 +149,42 @@ impl Index<SegmentId> for SegmentStore {
         &self.segments[index.0]
     }
 }
+impl Encode for SegmentStore {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.scramble.encode(out);
+        self.initial_state.encode(out);
+        self.segments.encode(out);
+        self.steps.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let ret = Self {
+            scramble: Vec::decode(input)?,
+            initial_state: PuzzleState::decode(input)?,
+            segments: Vec::decode(input)?,
+            steps: Vec::decode(input)?,
+        };
+        // Check that all IDs are in range.
+        let ids = ret.segments.iter().map(|s| s.previous_segment);
+        let mut ids = ids.chain(ret.steps.iter().flatten().copied());
+        (!ret.segments.is_empty() && ids.all(|id| id.0 < ret.segments.len())).then_some(ret)
+    }
+}
+
 impl SegmentStore {
     pub fn new(scramble: Vec<Twist>) -> Self {
         Self {
             scramble,
+            initial_state: PuzzleState::default(),
             segments: vec![Segment::default()],
             steps: vec![vec![SegmentId::INIT]],
         }
     }
+    pub fn from_state(initial_state: PuzzleState) -> Self {
+        Self {
+            initial_state,
+            ..Self::new(vec![])
+        }
+    }
 
     pub fn add_segments(&mut self, step: usize, segments: Vec<Segment>) {
         let start = self.segments.len();
@@ -132,23 +205,75 @@ impl SegmentStore {
         }
     }
 
-    pub fn solution_twists_for_segment(&self, id: SegmentId) -> Vec<Twist> {
-        self.twists_for_segment(&[], id)
-    }
-    pub fn all_prior_twists_for_segment(&self, id: SegmentId) -> Vec<Twist> {
-        self.twists_for_segment(&self.scramble, id)
-    }
-    fn twists_for_segment(&self, init: &[Twist], mut id: SegmentId) -> Vec<Twist> {
-        let mut reversed_twists = vec![];
+    pub fn solution_twists_for_segment(&self, mut id: SegmentId) -> Vec<Twist> {
+        let mut twists = vec![];
         while id != SegmentId::INIT {
             let segment = &self[id];
-            reversed_twists.extend(segment.segment_twists.into_iter().rev());
+            twists.extend(segment.segment_twists.into_iter().rev());
             id = segment.previous_segment;
         }
-        init.iter()
-            .chain(reversed_twists.iter().rev())
-            .copied()
-            .collect()
+        twists.reverse();
+        twists
+    }
+    /// Returns the scramble followed by the solution twists up to the end of a
+    /// segment.
+    pub fn all_prior_twists_for_segment(&self, id: SegmentId) -> Vec<Twist> {
+        let mut ret = self.scramble.clone();
+        ret.extend(self.solution_twists_for_segment(id));
+        ret
+    }
+    /// Returns the state of the puzzle after the scramble.
+    pub fn scrambled_state(&self) -> PuzzleState {
+        let mut ret = self.initial_state.clone();
+        ret.do_twists(&self.scramble);
+        ret
+    }
+
+    /// Removes every segment that is not reachable from a segment in the
+    /// latest step, and compacts segment IDs so that they are contiguous.
+    ///
+    /// IDs from before this call are invalidated, except for
+    /// [`SegmentId::INIT`]. Returns the number of segments removed.
+    pub fn collect_garbage(&mut self) -> usize {
+        let Some(latest_step) = self.steps.last() else {
+            return 0;
+        };
+
+        let mut is_reachable = vec![false; self.segments.len()];
+        is_reachable[SegmentId::INIT.0] = true;
+        for &id in latest_step {
+            let mut id = id;
+            while !is_reachable[id.0] {
+                is_reachable[id.0] = true;
+                id = self[id].previous_segment;
+            }
+        }
+
+        // Removing segments preserves the order of the rest, so the new IDs are
+        // still sorted.
+        let mut new_ids = vec![None; self.segments.len()];
+        let mut new_len = 0;
+        for (new_id, _) in std::iter::zip(&mut new_ids, &is_reachable).filter(|(_, r)| **r) {
+            *new_id = Some(SegmentId(new_len));
+            new_len += 1;
+        }
+        let removed_count = self.segments.len() - new_len;
+
+        self.segments = std::mem::take(&mut self.segments)
+            .into_iter()
+            .zip(&is_reachable)
+            .filter(|(_, is_reachable)| **is_reachable)
+            .map(|(segment, _)| Segment {
+                previous_segment: new_ids[segment.previous_segment.0]
+                    .expect("previous segment is reachable"),
+                ..segment
+            })
+            .collect();
+        for step in &mut self.steps {
+            *step = step.iter().filter_map(|id| new_ids[id.0]).collect();
+        }
+
+        removed_count
     }
 
     pub fn best_solutions_so_far(&self) -> Option<&Vec<SegmentId>> {
@@ -159,3 +284,56 @@ impl SegmentStore {
         self.steps.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_garbage() {
+        let twists = &RUBIKS_4D.twists;
+        let mut store = SegmentStore::new(vec![twists[0]]);
+
+        // Each step continues the first and last segments of the previous
+        // step, so the rest become unreachable.
+        for step in 1..=4 {
+            let prev_ids = store.segment_ids_for_step(step - 1).to_vec();
+            let new_segments = [prev_ids[0], *prev_ids.last().unwrap()]
+                .into_iter()
+                .flat_map(|id| {
+                    let segment = store[id].next_step(id);
+                    (1..=3).map(move |i| {
+                        segment
+                            .push_twist(&RUBIKS_4D, Metric::Stm, twists[step * 10 + i], None)
+                            .unwrap()
+                    })
+                })
+                .collect();
+            store.add_segments(step, new_segments);
+        }
+
+        let latest_ids = store.best_solutions_so_far().unwrap().clone();
+        let expected = latest_ids
+            .iter()
+            .map(|&id| (store.solution_twists_for_segment(id), store[id].clone()))
+            .collect::<Vec<_>>();
+
+        let old_len = store.segments.len();
+        let removed_count = store.collect_garbage();
+        assert!(removed_count > 0);
+        assert_eq!(old_len - removed_count, store.segments.len());
+        assert_eq!(0, store.collect_garbage());
+
+        let latest_ids = store.best_solutions_so_far().unwrap().clone();
+        assert_eq!(expected.len(), latest_ids.len());
+        for (&id, (expected_twists, expected_segment)) in std::iter::zip(&latest_ids, &expected) {
+            assert_eq!(*expected_twists, store.solution_twists_for_segment(id));
+            assert_eq!(expected_segment.state, store[id].state);
+            assert!(id.0 < store.segments.len());
+        }
+        assert_eq!(
+            twists[0..1],
+            store.all_prior_twists_for_segment(SegmentId::INIT)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::{Segment, SolutionMetadata};
+use crate::sim::*;
+
+/// Canonical representative of a [`BlockSet`] and its [`SolutionMetadata`] up
+/// to rotation of the whole puzzle and indistinguishable attitudes of blocks.
+///
+/// Two states with the same key are solved by the same twist sequences, up to
+/// the rotation returned by [`canonicalize()`]. The bidirectional search uses
+/// these keys to meet states that are rotations of each other. Steps of the
+/// main search don't deduplicate segments by key, since segments that are
+/// rotations of each other still lead to different solutions.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CanonicalKey {
+    pub state: BlockSet,
+    pub meta: SolutionMetadata,
+}
+impl CanonicalKey {
+    fn new(state: BlockSet, meta: SolutionMetadata, ndim: usize) -> Self {
+        Self {
+            state: state.with_canonical_attitudes(ndim).sorted(),
+            meta,
+        }
+    }
+}
+
+/// Returns the canonical representative of `state` and `meta` up to the
+/// rotations in `symmetries`, along with the rotation that takes the original
+/// to the canonical representative.
+///
+/// `symmetries` must be a group (typically [`Puzzle::rotations`]) or a subgroup
+/// of it, such as the stabilizer of a target state.
+///
+/// To take a twist sequence that solves the canonical representative back to
+/// the original frame, transform each twist by the inverse of the returned
+/// rotation.
+pub fn canonicalize(
+    state: BlockSet,
+    meta: SolutionMetadata,
+    symmetries: &[ElemId],
+    ndim: usize,
+) -> (CanonicalKey, ElemId) {
+    symmetries
+        .iter()
+        .map(|&rot| {
+            let key = CanonicalKey::new(rot.transform(state), rot.transform(meta), ndim);
+            (key, rot)
+        })
+        .min()
+        .unwrap_or((CanonicalKey::new(state, meta, ndim), IDENT))
+}
+
+impl Segment {
+    /// Returns the canonical key for the segment's state and metadata, along
+    /// with the rotation that reaches it. See [`canonicalize()`].
+    pub fn canonical_key(&self, puzzle: &Puzzle) -> (CanonicalKey, ElemId) {
+        canonicalize(self.state, self.meta, &puzzle.rotations, puzzle.ndim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn scrambled_state(twists: &[Twist]) -> BlockSet {
+        let block = Block::new_solved([], [R, U, F, O]).unwrap();
+        BlockSet::default()
+            .add_block_with_setup_moves(&RUBIKS_4D, twists, block)
+            .unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_canonicalize_rotated_state(
+            twist_indices in prop::collection::vec(0..RUBIKS_4D.twists.len(), 0..8),
+            rot_index in 0..192_usize,
+            next_twist_index in 0..RUBIKS_4D.twists.len(),
+        ) {
+            let twists: Vec<Twist> = twist_indices.iter().map(|&i| RUBIKS_4D.twists[i]).collect();
+            let rot = HYPERCUBE_ROTATIONS[rot_index];
+            let meta = SolutionMetadata::default();
+
+            let state = scrambled_state(&twists);
+            let rotated = rot.transform(state);
+
+            let (key, r1) = canonicalize(state, meta, &RUBIKS_4D.rotations, 4);
+            let (rotated_key, r2) = canonicalize(rotated, meta, &RUBIKS_4D.rotations, 4);
+            prop_assert_eq!(key, rotated_key);
+            prop_assert_eq!(r1.transform(state).with_canonical_attitudes(4).sorted(), key.state);
+            prop_assert_eq!((r2 * rot).transform(state).with_canonical_attitudes(4).sorted(), key.state);
+
+            // Twisting commutes with rotation. Block merging depends on the
+            // order of blocks, so don't sort them here.
+            let twist = RUBIKS_4D.twists[next_twist_index];
+            let twisted = state.do_twist(twist, &RUBIKS_4D).unwrap();
+            let rotated_then_twisted = rotated.do_twist(rot.transform(twist), &RUBIKS_4D).unwrap();
+            prop_assert_eq!(rot.transform(twisted), rotated_then_twisted);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::{
+    GripSet, HYPERCUBE_GRIPS, INDICES_BY_PIECE_TYPE, PuzzleState, Twist, Vec4, orientation_modulus,
+    piece_index, piece_name, piece_orientation, piece_position, sticker_grips,
+};
+
+/// Summary of what an algorithm does to a solved puzzle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlgorithmAnalysis {
+    /// Number of times the algorithm must be repeated to solve the puzzle
+    /// again.
+    pub order: usize,
+    /// Cycles of ridges, edges, and corners moved by the algorithm. Each cycle
+    /// lists piece positions, and the piece at each position moves to the
+    /// next one. Pieces that stay in place are not included.
+    pub cycles: [Vec<Vec<Vec4>>; 3],
+    /// Original position of each piece that ends up with nonzero orientation,
+    /// along with that orientation. Ridges and edges have orientation 0 or 1,
+    /// and corners 0, 1, or 2. See [`PuzzleState::is_solvable()`].
+    pub orientation_changes: Vec<(Vec4, u8)>,
+    /// Grips with every piece still solved.
+    pub untouched_grips: GripSet,
+}
+impl AlgorithmAnalysis {
+    /// Analyzes the algorithm `twists`.
+    pub fn new(twists: &[Twist]) -> Self {
+        Self::from_state(&PuzzleState::from_twists(twists))
+    }
+
+    /// Analyzes the algorithm that produces `state` from a solved puzzle.
+    pub fn from_state(state: &PuzzleState) -> Self {
+        let attitudes = state.piece_attitudes();
+        let original_index =
+            |index: usize| piece_index(attitudes[index].inv() * piece_position(index)).unwrap();
+
+        let mut destinations = [0; 72];
+        for index in 0..72 {
+            destinations[original_index(index)] = index;
+        }
+        let cycles = INDICES_BY_PIECE_TYPE.each_ref().map(|indices| {
+            let mut seen = [false; 72];
+            let mut cycles = vec![];
+            for &start in indices {
+                if seen[start] || destinations[start] == start {
+                    continue;
+                }
+                let cycle = std::iter::successors(Some(start), |&i| {
+                    Some(destinations[i]).filter(|&next| next != start)
+                })
+                .inspect(|&i| seen[i] = true)
+                .map(piece_position)
+                .collect();
+                cycles.push(cycle);
+            }
+            cycles
+        });
+
+        let orientation_changes = (INDICES_BY_PIECE_TYPE.iter().flatten())
+            .filter_map(|&index| {
+                let orientation = piece_orientation(index, attitudes[index]);
+                debug_assert!(orientation < orientation_modulus(index));
+                let original_pos = piece_position(original_index(index));
+                (orientation != 0).then_some((original_pos, orientation))
+            })
+            .collect();
+
+        let is_piece_solved = |index: usize| {
+            let pos = piece_position(index);
+            sticker_grips(pos).all(|g| attitudes[index] * g.vec() == g.vec())
+        };
+        let untouched_grips = HYPERCUBE_GRIPS
+            .into_iter()
+            .filter(|g| {
+                (0..72)
+                    .filter(|&i| piece_position(i)[g.axis()] == g.signum())
+                    .all(is_piece_solved)
+            })
+            .collect();
+
+        let mut order = 1;
+        let mut power = state.clone();
+        while !power.is_solved() {
+            power = power.then(state);
+            order += 1;
+        }
+
+        Self {
+            order,
+            cycles,
+            orientation_changes,
+            untouched_grips,
+        }
+    }
+}
+
+impl fmt::Display for AlgorithmAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Order: {}", self.order)?;
+        for (name, cycles) in ["Ridge", "Edge", "Corner"].iter().zip(&self.cycles) {
+            write!(f, "{name} cycles:")?;
+            if cycles.is_empty() {
+                write!(f, " none")?;
+            }
+            for cycle in cycles {
+                write!(
+                    f,
+                    " ({})",
+                    cycle.iter().map(|&pos| piece_name(pos)).join(" ")
+                )?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "Orientation changes:")?;
+        if self.orientation_changes.is_empty() {
+            write!(f, " none")?;
+        }
+        for &(pos, orientation) in &self.orientation_changes {
+            write!(f, " {}+{orientation}", piece_name(pos))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Untouched grips: {}", self.untouched_grips)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_twists;
+
+    #[test]
+    fn test_algorithm_analysis() {
+        let solved = AlgorithmAnalysis::new(&[]);
+        assert_eq!(solved.order, 1);
+        assert!(solved.cycles.iter().all(|c| c.is_empty()));
+        assert!(solved.orientation_changes.is_empty());
+        assert_eq!(solved.untouched_grips, GripSet::ALL);
+
+        let quarter = AlgorithmAnalysis::new(&parse_twists("RU"));
+        assert_eq!(quarter.order, 4);
+        assert_eq!(quarter.untouched_grips, GripSet::NONE + crate::L);
+        for cycles in &quarter.cycles {
+            assert!(cycles.iter().all(|cycle| cycle.len() == 4));
+        }
+
+        let half = AlgorithmAnalysis::new(&parse_twists("RU2"));
+        assert_eq!(half.order, 2);
+        for cycles in &half.cycles {
+            assert!(cycles.iter().all(|cycle| cycle.len() == 2));
+        }
+
+        // Only pieces in the U layer are moved, so D is untouched.
+        let [a, b] = parse_twists("RU UF")[..] else {
+            unreachable!()
+        };
+        let commutator = AlgorithmAnalysis::new(&[a, b, a.inv(), b.inv()]);
+        assert_eq!(commutator.order, 63);
+        assert!(commutator.cycles[0].is_empty());
+        assert_eq!(commutator.cycles[1].iter().map(Vec::len).collect_vec(), [7]);
+        assert_eq!(
+            commutator.cycles[2].iter().map(Vec::len).collect_vec(),
+            [3, 3]
+        );
+        assert_eq!(commutator.untouched_grips, GripSet::NONE + crate::D);
+        assert_eq!(
+            commutator.order,
+            AlgorithmAnalysis::new(&[b, a, b.inv(), a.inv()]).order,
+        );
+    }
+}
@@ -4,6 +4,7 @@ use std::ops::Mul;
 use super::layer_mask::{GripStatus, PackedLayers};
 use super::piece::Piece;
 use crate::StackVec;
+use crate::sim::Puzzle;
 use crate::sim::common::*;
 
 /// Block of pieces with compatible attitudes, displayed as
@@ -53,6 +54,14 @@ impl Block {
         Some(Self { layers, attitude })
     }
 
+    /// Constructs a block from its layers and attitude, or returns `None` if
+    /// the block is empty on some axes but not others. A completely empty
+    /// block is allowed because it is used as a placeholder.
+    pub(crate) fn from_parts(layers: PackedLayers, attitude: ElemId) -> Option<Self> {
+        let is_valid = layers == PackedLayers::EMPTY || !layers.is_empty_on_any_axis();
+        is_valid.then_some(Self { layers, attitude })
+    }
+
     pub fn layers(self) -> PackedLayers {
         self.layers
     }
@@ -84,6 +93,21 @@ impl Block {
     pub fn blocked_grips(self) -> GripSet {
         self.grips_with_status(GripStatus::Blocked)
     }
+    /// Returns the pieces in the block, in their current positions.
+    pub fn pieces(self, puzzle: &Puzzle) -> impl Iterator<Item = Piece> {
+        let solved = self.at_solved();
+        let mut blocks = vec![solved];
+        for g in (puzzle.grip_set() & solved.blocked_grips()).iter() {
+            blocks = blocks
+                .into_iter()
+                .flat_map(|b| b.split(g))
+                .flatten() // Option<T> -> T
+                .collect();
+        }
+        blocks
+            .into_iter()
+            .map(move |b| self.attitude * Piece::new_solved(b.active_grips().iter()))
+    }
     pub fn is_fully_blocked_on_axis(self, axis: usize) -> bool {
         self.layers.is_fully_blocked_on_axis(axis)
     }
@@ -143,6 +167,18 @@ impl Block {
             .iter()
             .map(|&rot| rot * self.attitude)
     }
+    /// Returns the same block with the smallest of its indistinguishable
+    /// attitudes, so that blocks in the same position compare equal.
+    #[must_use]
+    pub fn with_canonical_attitude(self, ndim: usize) -> Self {
+        Self {
+            layers: self.layers,
+            attitude: self
+                .indistinguishable_attitudes(ndim)
+                .min()
+                .unwrap_or(self.attitude),
+        }
+    }
     /// Returns the set of grips constructed by taking all indistinguishable
     /// attitudes for the block in its current position and mutliplying each one
     /// by `g`. Duplicates are not included.
@@ -170,9 +206,11 @@ impl Block {
             return only_g();
         }
 
+        // Indistinguishable attitudes can take `g` to any grip on an axis that
+        // isn't blocked.
         StackVec::from_iter(
             (0..4)
-                .filter(|i| (blocked_axes_mask >> i) & 1 != 0)
+                .filter(|i| (blocked_axes_mask >> (i * 4)) & 1 == 0)
                 .flat_map(GripId::pair_on_axis),
         )
         .unwrap()
@@ -188,8 +226,14 @@ impl Block {
         })
     }
 
-    pub fn try_merge(self, other: Self, ndim: usize) -> Option<Self> {
-        let layers = self.layers.try_merge_with(other.layers)?.0;
+    /// Merges the blocks if possible. Blocks that aren't connected are only
+    /// merged if `puzzle` has slice twists.
+    pub fn try_merge(self, other: Self, puzzle: &Puzzle) -> Option<Self> {
+        let ndim = puzzle.ndim;
+        let layers = self
+            .layers
+            .try_merge_with(other.layers, puzzle.slice_twists)?
+            .0;
 
         let [head, body] = if self.layers.active_grip_count() > other.layers.active_grip_count() {
             [self, other]
@@ -223,11 +267,29 @@ impl fmt::Display for Block {
     }
 }
 
+impl TransformByElem for Block {
+    #[inline]
+    fn transform_by(self, elem: ElemId) -> Self {
+        Block {
+            layers: elem * self.layers,
+            attitude: elem.transform(self.attitude),
+        }
+    }
+}
+
 impl Mul<Block> for Twist {
     type Output = [Option<Block>; 2];
 
     fn mul(self, rhs: Block) -> Self::Output {
-        let [inside, outside] = rhs.split(self.grip);
+        let [inside, outside] = rhs
+            .layers
+            .split_layers(self.grip(), self.layers())
+            .map(|layers| {
+                Some(Block {
+                    layers: layers?,
+                    attitude: rhs.attitude,
+                })
+            });
         [
             inside.map(|b| Block {
                 layers: self.transform * b.layers,
@@ -278,7 +340,8 @@ mod test {
     }
 
     fn test_merge_blocks(mut b1: Block, b2: Block) {
-        let ndim = 4;
+        let puzzle = &*crate::RUBIKS_4D;
+        let ndim = puzzle.ndim;
 
         assert!(!b1.layers.is_empty_on_any_axis());
         assert!(!b2.layers.is_empty_on_any_axis());
@@ -290,8 +353,8 @@ mod test {
         let mut b1_attitudes = b1.indistinguishable_attitudes(ndim).collect_vec();
         let b2_attitudes = b2.indistinguishable_attitudes(ndim).collect_vec();
         if !b1_attitudes.iter().any(|a| b2_attitudes.contains(a)) {
-            assert_eq!(None, b1.try_merge(b2, ndim));
-            assert_eq!(None, b2.try_merge(b1, ndim)); // commutativity
+            assert_eq!(None, b1.try_merge(b2, puzzle));
+            assert_eq!(None, b2.try_merge(b1, puzzle)); // commutativity
             b1.attitude = b2.attitude;
             b1_attitudes = b1.indistinguishable_attitudes(ndim).collect();
         }
@@ -308,8 +371,8 @@ mod test {
             num_mergeable_axes += disjoint as usize;
         }
 
-        let actual_merged = b1.try_merge(b2, ndim);
-        assert_eq!(actual_merged, b2.try_merge(b1, ndim)); // commutativity
+        let actual_merged = b1.try_merge(b2, puzzle);
+        assert_eq!(actual_merged, b2.try_merge(b1, puzzle)); // commutativity
 
         assert_eq!(
             num_same_axes == 3 && num_mergeable_axes == 1,
@@ -351,7 +414,7 @@ mod test {
                     for case in cases {
                         assert_eq!(center_orientations, count(case, ndim));
                     }
-                    for &g2 in grips.into_iter().filter(|&g2| g2.axis() != g1.axis()) {
+                    for &g2 in grips.iter().filter(|&g2| g2.axis() != g1.axis()) {
                         let blocked = cases.map(|b| b.expand_to_active_grip(g2));
                         let active = blocked.map(|b| b.restrict_to_active_grip(g2).unwrap());
                         let double_blocked =
@@ -361,7 +424,7 @@ mod test {
                             assert_eq!(ridge_orientations, count(case, ndim));
                         }
                         for &g3 in grips
-                            .into_iter()
+                            .iter()
                             .filter(|&g3| g3.axis() != g1.axis() && g3.axis() != g2.axis())
                         {
                             for b in cases.as_flattened() {
@@ -1,6 +1,8 @@
 use std::fmt;
 use std::ops::{BitAnd, BitOr, BitXor, Mul};
 
+use itertools::Itertools;
+
 use crate::sim::common::*;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -22,7 +24,7 @@ impl PackedLayers {
     pub const ALL: Self = Self::from_u16(0b_0111_0111_0111_0111);
 
     #[inline]
-    const fn to_u16(self) -> u16 {
+    pub(crate) const fn to_u16(self) -> u16 {
         u16::from_ne_bytes(self.0)
     }
     #[inline]
@@ -129,6 +131,23 @@ impl PackedLayers {
             self.restrict_to_inactive_grip(g),
         ]
     }
+    /// Returns `[inside, outside]` for a twist of `layers` on `g`.
+    #[must_use]
+    #[inline]
+    pub fn split_layers(self, g: GripId, layers: TwistLayers) -> [Option<Self>; 2] {
+        let bits = self.to_u16();
+        let twisted = twisted_layers_mask(g, layers);
+        let other_axes = !(0b0111 << (g.axis() * 4));
+        [
+            Self::from_u16(bits & (twisted | other_axes)).if_nonempty_on_axis(g.axis()),
+            Self::from_u16(bits & !twisted).if_nonempty_on_axis(g.axis()),
+        ]
+    }
+    /// Returns whether a twist of `layers` on `g` moves any pieces in `self`.
+    #[inline]
+    pub const fn is_moved_by(self, g: GripId, layers: TwistLayers) -> bool {
+        self.to_u16() & twisted_layers_mask(g, layers) != 0
+    }
 
     pub fn is_subset_of(self, other: Self) -> bool {
         self.to_u16() & !other.to_u16() == 0
@@ -136,8 +155,12 @@ impl PackedLayers {
 
     /// Merges the blocks if possible. Returns the merged block and the axis
     /// along which they were merged.
+    ///
+    /// If `allow_disconnected` is `false`, blocks on opposite outer layers
+    /// aren't merged because only a slice twist could separate them from the
+    /// middle layer.
     #[must_use]
-    pub fn try_merge_with(self, other: Self) -> Option<(Self, usize)> {
+    pub fn try_merge_with(self, other: Self, allow_disconnected: bool) -> Option<(Self, usize)> {
         let lhs = self.to_u16();
         let rhs = other.to_u16();
         let layer_difference = lhs ^ rhs;
@@ -155,13 +178,53 @@ impl PackedLayers {
             return None; // `self` and `other` overlap
         }
 
-        if lhs_axis_bits | rhs_axis_bits == 0b101 {
-            return None; // disconnected blocks not allowed (but they could be, if we had slice moves)
+        if lhs_axis_bits | rhs_axis_bits == 0b101 && !allow_disconnected {
+            return None; // disconnected blocks not allowed without slice moves
         }
 
         Some((self | other, merge_axis))
     }
 
+    /// Returns each pair of adjacent pieces `(a, b)` where `a` is in `self` and
+    /// `b` is in `other`. Pieces are given by the grips they are in.
+    pub fn adjacent_pieces(self, other: Self) -> impl Iterator<Item = (GripSet, GripSet)> {
+        let layer_grips = |axis: usize, layer: usize| -> GripSet {
+            let [pos, neg] = GripId::pair_on_axis(axis);
+            match layer {
+                0 => GripSet::from_iter([pos]),
+                2 => GripSet::from_iter([neg]),
+                _ => GripSet::NONE,
+            }
+        };
+        let layers_in = |bits: u8| (0..3).filter(move |i| bits >> i & 1 != 0);
+
+        (0..4).flat_map(move |axis| {
+            let overlapping_pieces = (0..4)
+                .filter(|&a| a != axis)
+                .map(|a| {
+                    let overlap = self.bits_for_axis(a) & other.bits_for_axis(a);
+                    layers_in(overlap).map(move |layer| layer_grips(a, layer))
+                })
+                .multi_cartesian_product()
+                .map(|grips| grips.into_iter().fold(GripSet::NONE, |a, b| a | b));
+
+            let self_bits = self.bits_for_axis(axis);
+            let other_bits = other.bits_for_axis(axis);
+            let adjacent_layers = [(0, 1), (1, 0), (1, 2), (2, 1)]
+                .into_iter()
+                .filter(move |&(l1, l2)| self_bits >> l1 & 1 != 0 && other_bits >> l2 & 1 != 0);
+
+            itertools::iproduct!(adjacent_layers, overlapping_pieces).map(
+                move |((l1, l2), common)| {
+                    (
+                        common | layer_grips(axis, l1),
+                        common | layer_grips(axis, l2),
+                    )
+                },
+            )
+        })
+    }
+
     /// Returns the positive grips on the separating axes of the blocks.
     pub fn separating_axes(self, other: Self) -> GripSet {
         let diff = self ^ other;
@@ -181,16 +244,29 @@ impl PackedLayers {
         let pos_bits = bits & 0b_0001_0001_0001_0001;
         let mid_bits = bits & 0b_0010_0010_0010_0010;
         let neg_bits = bits & 0b_0100_0100_0100_0100;
-        let pos_grips_active = pos_bits & (!mid_bits >> 1);
-        let neg_grips_active = neg_bits & (!mid_bits << 1);
+        let pos_grips_active = pos_bits & (!mid_bits >> 1) & (!neg_bits >> 2);
+        let neg_grips_active = neg_bits & (!mid_bits << 1) & (!pos_bits << 2);
         (pos_grips_active | neg_grips_active).count_ones()
     }
 
+    /// Returns the number of pieces in the block, which does not change when
+    /// the block is rotated.
+    #[inline]
+    pub const fn piece_count(self) -> u32 {
+        let mut ret = 1;
+        let mut axis = 0;
+        while axis < 4 {
+            ret *= self.bits_for_axis(axis).count_ones();
+            axis += 1;
+        }
+        ret
+    }
+
     /// Returns the status of a grip, or `None` if the layer mask is invalid.
     pub const fn grip_status(self, g: GripId) -> GripStatus {
         match self.bits_for_grip(g) {
             0b001 => GripStatus::Active,
-            0b011 | 0b111 => GripStatus::Blocked,
+            0b011 | 0b111 | 0b101 => GripStatus::Blocked,
             0b110 | 0b100 | 0b010 => GripStatus::Inactive,
             _ => panic!("invalid block"),
         }
@@ -211,6 +287,12 @@ const fn inactive_grip_mask(g: GripId) -> u16 {
     g.hint_assert_in_bounds();
     (0b0111 << (g.axis() * 4)) ^ active_grip_mask(g)
 }
+const fn twisted_layers_mask(g: GripId, layers: TwistLayers) -> u16 {
+    g.hint_assert_in_bounds();
+    let bits = layers.mask() & 0b111;
+    let bits = if g.id() & 1 == 0 { bits } else { rev3(bits) };
+    (bits as u16) << (g.axis() * 4)
+}
 
 impl fmt::Debug for PackedLayers {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -302,7 +384,7 @@ mod tests {
     proptest! {
         #[test]
         fn proptest_merge_layers(xs in prop::array::uniform8(0..6_usize)) {
-            test_merge_layers(xs)
+            test_merge_layers(xs);
         }
     }
 
@@ -338,7 +420,7 @@ mod tests {
         });
 
         let expected = merge_grip.map(|g| (l1 | l2, g.axis()));
-        let actual = l1.try_merge_with(l2);
+        let actual = l1.try_merge_with(l2, false);
 
         assert_eq!(expected, actual);
     }
@@ -40,7 +40,14 @@ impl Mul<Piece> for Twist {
 
     fn mul(self, rhs: Piece) -> Self::Output {
         // GRIP THEORY
-        if rhs.grips.contains(self.grip) {
+        let layer = if rhs.grips.contains(self.grip()) {
+            0
+        } else if rhs.grips.contains(self.grip().opposite()) {
+            2
+        } else {
+            1
+        };
+        if self.layers().mask() >> layer & 1 != 0 {
             self.transform * rhs
         } else {
             rhs
@@ -24,7 +24,7 @@ impl BlockSet {
     /// Returns `None` if the twist failed because there are too many blocks to
     /// track.
     #[must_use]
-    pub fn do_twist(self, twist: Twist, ndim: usize) -> Option<Self> {
+    pub fn do_twist(self, twist: Twist, puzzle: &Puzzle) -> Option<Self> {
         let new_blocks = StackVec::<Block, { crate::MAX_BLOCKS }>::from_iter(
             self.blocks
                 .into_iter()
@@ -32,16 +32,16 @@ impl BlockSet {
                 .flatten(), // Option<T> -> T
         )?;
 
-        Some(Self { blocks: new_blocks }.merge_blocks(ndim))
+        Some(Self { blocks: new_blocks }.merge_blocks(puzzle))
     }
 
     #[must_use]
-    fn merge_blocks(mut self, ndim: usize) -> Self {
+    fn merge_blocks(mut self, puzzle: &Puzzle) -> Self {
         loop {
             let mut merged_blocks = StackVec::new();
             'b1: for b1 in self.blocks {
                 for b2 in &mut merged_blocks {
-                    if let Some(merged) = b1.try_merge(*b2, ndim) {
+                    if let Some(merged) = b1.try_merge(*b2, puzzle) {
                         *b2 = merged; // replace with merged block
                         continue 'b1;
                     }
@@ -61,14 +61,40 @@ impl BlockSet {
     /// TODO: it may be possible for this to get an "N-perm situation" which
     /// would be bad.
     #[must_use]
-    fn from_blocks(blocks: StackVec<Block, { crate::MAX_BLOCKS }>, ndim: usize) -> Self {
-        Self { blocks }.merge_blocks(ndim)
+    fn from_blocks(blocks: StackVec<Block, { crate::MAX_BLOCKS }>, puzzle: &Puzzle) -> Self {
+        Self { blocks }.merge_blocks(puzzle)
     }
 
     pub fn is_solved(self) -> bool {
         self.blocks.len() == 1
     }
 
+    /// Returns the same state with its blocks in sorted order, so that states
+    /// containing the same blocks compare equal.
+    #[must_use]
+    pub fn sorted(self) -> Self {
+        Self {
+            blocks: self.blocks.sorted_unstable(),
+        }
+    }
+
+    /// Returns the same state with each block using its canonical attitude. See
+    /// [`Block::with_canonical_attitude()`].
+    #[must_use]
+    pub fn with_canonical_attitudes(self, ndim: usize) -> Self {
+        Self {
+            blocks: self.blocks.map(|b| b.with_canonical_attitude(ndim)),
+        }
+    }
+
+    /// Returns the union of the layers of all the blocks.
+    pub fn combined_layers(self) -> PackedLayers {
+        self.blocks
+            .iter()
+            .map(|b| b.layers())
+            .fold(PackedLayers::EMPTY, |a, b| a | b)
+    }
+
     /// Applies `setup_moves` to each piece in `block` and then adds all the
     /// pieces into the puzzle state, except for the ones that are already in
     /// the puzzle state.
@@ -84,37 +110,66 @@ impl BlockSet {
         setup_moves: &[Twist],
         new_block: Block,
     ) -> Option<Self> {
-        let pieces_from_block = |block: Block| {
-            assert_eq!(block.attitude(), crate::IDENT);
-            let mut blocks = vec![block];
-            for g in (puzzle.grip_set() & block.blocked_grips()).iter() {
-                blocks = blocks
-                    .into_iter()
-                    .flat_map(|b| b.split(g))
-                    .flatten() // Option<T> -> T
-                    .collect();
-            }
-            blocks
-                .into_iter()
-                .map(|b| Piece::new_solved(b.active_grips().iter()))
-        };
+        self.add_block_from_state(puzzle, &PuzzleState::default(), setup_moves, new_block)
+    }
 
-        let mut new_pieces = pieces_from_block(new_block).collect::<HashSet<Piece>>();
-        for old_block in self.blocks {
-            for piece in pieces_from_block(old_block.at_solved()) {
-                new_pieces.remove(&piece);
-            }
-        }
+    /// Finds each piece in `block` in `state`, applies `setup_moves` to it, and
+    /// then adds all the pieces into the puzzle state, except for the ones
+    /// that are already in the puzzle state.
+    ///
+    /// Centers and the core aren't tracked by [`PuzzleState`], so they start
+    /// out solved.
+    ///
+    /// `block` must have the identity attitude.
+    ///
+    /// Returns `None` if the puzzle state would have more than
+    /// [`crate::MAX_BLOCKS`] blocks.
+    #[must_use]
+    pub fn add_block_from_state(
+        self,
+        puzzle: &Puzzle,
+        state: &PuzzleState,
+        setup_moves: &[Twist],
+        new_block: Block,
+    ) -> Option<Self> {
+        assert_eq!(new_block.attitude(), crate::IDENT);
 
-        let init_piece = |new_piece| setup_moves.iter().fold(new_piece, |p, &twist| twist * p);
+        // Keep the new pieces in order so that the way they get merged into
+        // blocks is deterministic.
+        let old_pieces = self
+            .blocks
+            .iter()
+            .flat_map(|old_block| old_block.at_solved().pieces(puzzle))
+            .collect::<HashSet<Piece>>();
+        let new_pieces = new_block
+            .pieces(puzzle)
+            .filter(|piece| !old_pieces.contains(piece));
+
+        let init_piece = |new_piece: Piece| {
+            let new_piece = if new_piece.grips.len() < 2 {
+                new_piece // center or core
+            } else {
+                let solved_pos = new_piece.grips.iter().map(|g| g.vec()).sum();
+                state.piece_attitudes()[state.find_piece(solved_pos)] * new_piece
+            };
+            setup_moves.iter().fold(new_piece, |p, &twist| twist * p)
+        };
 
         Some(Self::from_blocks(
             self.blocks
-                .extend(new_pieces.into_iter().map(init_piece).map(Block::from))?,
-            puzzle.ndim,
+                .extend(new_pieces.map(init_piece).map(Block::from))?,
+            puzzle,
         ))
     }
 }
+impl TransformByElem for BlockSet {
+    #[inline]
+    fn transform_by(self, elem: ElemId) -> Self {
+        Self {
+            blocks: self.blocks.map(|b| elem.transform(b)),
+        }
+    }
+}
 impl fmt::Display for BlockSet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "[")?;
@@ -126,9 +181,29 @@ impl fmt::Display for BlockSet {
 
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
+    use proptest::prelude::*;
+    use rand::SeedableRng;
+
     use crate::StackVec;
     use crate::sim::*;
 
+    /// Returns a piece tracked by `blocks` that isn't where `state` says it
+    /// is, if there is one.
+    fn misplaced_piece(blocks: BlockSet, puzzle: &Puzzle, state: &PuzzleState) -> Option<Piece> {
+        let pieces = blocks.blocks.into_iter().flat_map(|b| b.pieces(puzzle));
+        // Centers and the core aren't in `PuzzleState`.
+        pieces.filter(|piece| piece.grips.len() >= 2).find(|piece| {
+            let pos = piece.grips.iter().map(|g| g.vec()).sum();
+            let expected = state.piece_attitudes()[piece_index(pos).unwrap()];
+            // Merged blocks may rotate some pieces in ways that don't move
+            // their stickers.
+            let looks_same = (piece.grips.iter())
+                .all(|g| piece.attitude.inv() * g.vec() == expected.inv() * g.vec());
+            !looks_same
+        })
+    }
+
     #[test]
     fn test_puzzle_state() {
         let last_layer_algs = [
@@ -146,8 +221,6 @@ mod tests {
             (true, "R U R' U' R' F R2 U' R' U' R U R' F'"), // T perm
         ];
 
-        let ndim = 3;
-
         for (should_be_solved, last_layer_twist_seq) in last_layer_algs {
             let mut state = BlockSet {
                 blocks: StackVec::from_iter([Block::new_solved([], [U]).unwrap()]).unwrap(),
@@ -156,9 +229,118 @@ mod tests {
                 .split_ascii_whitespace()
                 .map(|s| TWISTS_FROM_NAME[s])
             {
-                state = state.do_twist(t, ndim).unwrap();
+                state = state.do_twist(t, &RUBIKS_3D).unwrap();
             }
             assert_eq!(should_be_solved, state.is_solved());
         }
     }
+
+    #[test]
+    fn test_slice_twists() {
+        let puzzle = &*RUBIKS_4D_WITH_SLICES;
+        let solved = BlockSet {
+            blocks: StackVec::from_iter([Block::new_solved([], []).unwrap()]).unwrap(),
+        };
+
+        let slice = TWISTS_FROM_NAME["RU"].with_layers(TwistLayers::MIDDLE);
+        let state = solved.do_twist(slice, puzzle).unwrap();
+        assert_eq!(state.blocks.len(), 2);
+        assert!(state.do_twist(slice.inv(), puzzle).unwrap().is_solved());
+
+        // Twisting both outer layers the same way leaves the outer layers
+        // solved relative to each other, but they're only connected through
+        // the middle layer.
+        let [r, l] = [R, L].map(|grip| Twist::new(grip, slice.transform));
+        let outer_twists = |puzzle| {
+            let state = solved.do_twist(r, puzzle).unwrap();
+            state.do_twist(l, puzzle).unwrap()
+        };
+        assert_eq!(outer_twists(puzzle).blocks.len(), 2);
+        assert_eq!(outer_twists(&RUBIKS_4D).blocks.len(), 3);
+        assert!(
+            outer_twists(puzzle)
+                .do_twist(slice, puzzle)
+                .unwrap()
+                .is_solved()
+        );
+    }
+
+    #[test]
+    fn test_add_block_from_state() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        for _ in 0..20 {
+            let scramble = RUBIKS_4D.random_moves(&mut rng, 8);
+            let (before, after) = scramble.split_at(4);
+            let state = PuzzleState::from_twists(before);
+            let scrambled = PuzzleState::from_twists(&scramble);
+            for block in [
+                Block::new_solved([], [R, U, F, O]).unwrap(),
+                Block::new_solved([L], [D, I]).unwrap(),
+            ] {
+                let from_setup_moves = BlockSet::default()
+                    .add_block_with_setup_moves(&RUBIKS_4D, &scramble, block)
+                    .unwrap();
+                let from_state = BlockSet::default()
+                    .add_block_from_state(&RUBIKS_4D, &state, after, block)
+                    .unwrap();
+                // Pieces other than centers are where they would be after the
+                // whole scramble, but the centers may be rotated differently
+                // and merge with more pieces.
+                assert_eq!(
+                    from_setup_moves.combined_layers(),
+                    from_state.combined_layers()
+                );
+                assert!(from_state.blocks.len() <= from_setup_moves.blocks.len());
+
+                // Every piece is where the whole scramble puts it.
+                for blocks in [from_setup_moves, from_state] {
+                    let piece = misplaced_piece(blocks, &RUBIKS_4D, &scrambled);
+                    assert_eq!(piece, None, "after {}", scramble.iter().join(" "));
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Checks that every piece tracked by a `BlockSet` is where
+        /// `PuzzleState` says it is.
+        #[test]
+        fn proptest_block_set_matches_puzzle_state(
+            slice_twists: bool,
+            block_index in 0..3_usize,
+            twists in prop::collection::vec(any::<prop::sample::Index>(), 0..30),
+        ) {
+            let puzzle = if slice_twists { &*RUBIKS_4D_WITH_SLICES } else { &*RUBIKS_4D };
+            let twists = twists.iter().map(|i| *i.get(&puzzle.twists)).collect_vec();
+            let block = [
+                Block::new_solved([], [L, D, B, I]),
+                Block::new_solved([O], []),
+                Block::new_solved([], [R, O]),
+            ][block_index]
+            .unwrap();
+
+            let mut blocks = BlockSet {
+                blocks: StackVec::from_iter([block]).unwrap(),
+            };
+            let mut state = PuzzleState::default();
+            for (i, &twist) in twists.iter().enumerate() {
+                let Some(new_blocks) = blocks.do_twist(twist, puzzle) else {
+                    break; // too many blocks to track
+                };
+                blocks = new_blocks;
+                state.do_twist(twist);
+
+                if let Some(piece) = misplaced_piece(blocks, puzzle, &state) {
+                    prop_assert!(
+                        false,
+                        "piece {} is wrong after {}",
+                        piece,
+                        twists[..=i].iter().join(" "),
+                    );
+                }
+            }
+        }
+    }
 }
@@ -78,11 +78,21 @@ impl GripId {
         b"RLUDFBOI"[self.0 as usize] as char
     }
 
+    /// Returns the grip named by `c`, which is one of `RLUDFBOI`.
+    pub fn from_char(c: char) -> Option<Self> {
+        HYPERCUBE_GRIPS.into_iter().find(|g| g.char() == c)
+    }
+
     pub fn vec(self) -> Vec4 {
         let mut ret = ZERO;
         ret[self.axis()] = self.signum();
         ret
     }
+    /// Returns the grip whose [`GripId::vec()`] is `v`, or `None` if `v` is not
+    /// a unit vector along an axis.
+    pub fn from_vec(v: Vec4) -> Option<Self> {
+        HYPERCUBE_GRIPS.into_iter().find(|g| g.vec() == v)
+    }
 
     pub fn transforms(self, subgroup: &[ElemId]) -> Vec<ElemId> {
         group::stabilizer(subgroup.iter().copied(), self.vec())
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 
 use itertools::Itertools;
 
@@ -17,47 +18,216 @@ pub static TWISTS_FROM_NAME: HashMap<String, Twist> =
         .map(|(t, s)| (s.clone(), *t))
         .collect();
 
-#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Twist {
-    pub grip: GripId,
+    /// Grip ID in the low 3 bits and [`TwistLayers`] mask in the high 5 bits,
+    /// so that a twist fits in 2 bytes and a [`crate::Segment`] in 128.
+    grip_and_layers: u8,
     pub transform: ElemId,
 }
+impl Default for Twist {
+    fn default() -> Self {
+        Self::new(GripId::default(), ElemId::default())
+    }
+}
+impl PartialOrd for Twist {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Twist {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let key = |t: &Self| (t.grip(), t.transform, t.layers());
+        key(self).cmp(&key(other))
+    }
+}
 impl Twist {
+    /// Constructs a twist of the outer layer of `grip`.
     pub fn new(grip: GripId, transform: ElemId) -> Self {
-        Self { grip, transform }
+        Self {
+            grip_and_layers: grip.id(),
+            transform,
+        }
+        .with_layers(TwistLayers::OUTER)
+    }
+
+    /// Returns the grip that is twisted.
+    #[inline]
+    pub fn grip(self) -> GripId {
+        GripId::new(self.grip_and_layers & 0b111)
+    }
+
+    /// Returns the layers that are twisted.
+    #[inline]
+    pub fn layers(self) -> TwistLayers {
+        TwistLayers(self.grip_and_layers >> 3)
     }
 
     #[must_use]
     pub fn inv(self) -> Self {
-        Self::new(self.grip, self.transform.inv())
+        Self {
+            transform: self.transform.inv(),
+            ..self
+        }
+    }
+
+    /// Returns the same twist on different layers.
+    #[must_use]
+    pub fn with_layers(self, layers: TwistLayers) -> Self {
+        Self {
+            grip_and_layers: self.grip_and_layers & 0b111 | layers.0 << 3,
+            ..self
+        }
+    }
+
+    /// Returns whether the twist moves every layer of a 3x3x3x3, which rotates
+    /// the whole puzzle.
+    pub fn is_rotation(self) -> bool {
+        self.layers() == TwistLayers::ALL
     }
 }
 impl fmt::Debug for Twist {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}[{}]", self.grip, self.transform)
+        self.layers().fmt_prefix(f)?;
+        write!(f, "{}[{}]", self.grip(), self.transform)
     }
 }
 impl fmt::Display for Twist {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outer = self.with_layers(TwistLayers::OUTER);
         if crate::USE_3D_TWIST_NAMES
-            && let Some(s) = TWIST_NAMES_3D.get(self)
+            && let Some(s) = TWIST_NAMES_3D.get(&outer)
         {
+            self.layers().fmt_prefix(f)?;
             write!(f, "{s}")
-        } else if let Some(s) = TWIST_NAMES_4D.get(self) {
+        } else if let Some(s) = TWIST_NAMES_4D.get(&outer) {
+            self.layers().fmt_prefix(f)?;
             write!(f, "{s}")
         } else {
             write!(f, "{self:?}")
         }
     }
 }
+impl FromStr for Twist {
+    type Err = &'static str;
+
+    /// Parses a twist name such as `IU`, optionally preceded by layers in
+    /// braces such as `{2}IU` or `{1-2}IU`. Layers must be in range for a
+    /// puzzle with [`crate::Puzzle::LAYER_COUNT`] layers.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (layers, name) = match s.strip_prefix('{') {
+            Some(rest) => {
+                let (layers, name) = rest.split_once('}').ok_or("missing `}`")?;
+                let layers: TwistLayers = layers.parse()?;
+                if !layers.fits(crate::Puzzle::LAYER_COUNT) {
+                    return Err("layer out of range; the puzzle has 3 layers");
+                }
+                (layers, name)
+            }
+            None => (TwistLayers::OUTER, s),
+        };
+        let twist = TWISTS_FROM_NAME.get(name).ok_or("unknown twist name")?;
+        Ok(twist.with_layers(layers))
+    }
+}
+
+/// Layers moved by a twist, as a bitmask where bit `i` is the `i`th layer
+/// counting inward from the grip.
+///
+/// On a 3x3x3x3, [`Self::MIDDLE`] is a slice twist and [`Self::WIDE`] is a
+/// two-layer twist, and [`Self::ALL`] rotates the whole puzzle. Other masks
+/// are allowed but redundant: `0b100` is the same as twisting the opposite
+/// grip.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TwistLayers(u8);
+impl Default for TwistLayers {
+    fn default() -> Self {
+        Self::OUTER
+    }
+}
+impl TwistLayers {
+    /// Outer layer only, which is an ordinary twist.
+    pub const OUTER: Self = Self(0b001);
+    /// Middle layer of a 3x3x3x3.
+    pub const MIDDLE: Self = Self(0b010);
+    /// Outer two layers.
+    pub const WIDE: Self = Self(0b011);
+    /// All layers of a 3x3x3x3.
+    pub const ALL: Self = Self(0b111);
+
+    /// Maximum number of layers, which is limited by the spare bits in
+    /// [`Twist`].
+    pub const MAX_LAYER_COUNT: u8 = 5;
+
+    /// Constructs layers from a bitmask, or returns `None` if it is empty or
+    /// has layers past [`Self::MAX_LAYER_COUNT`].
+    pub const fn new(mask: u8) -> Option<Self> {
+        if mask == 0 || mask >> Self::MAX_LAYER_COUNT != 0 {
+            None
+        } else {
+            Some(Self(mask))
+        }
+    }
+
+    /// Returns whether every layer is in range for a puzzle with
+    /// `layer_count` layers.
+    pub const fn fits(self, layer_count: u8) -> bool {
+        (self.0 as u32) >> layer_count == 0
+    }
+
+    /// Returns the bitmask of layers.
+    pub const fn mask(self) -> u8 {
+        self.0
+    }
+
+    fn fmt_prefix(self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self == Self::OUTER {
+            Ok(())
+        } else {
+            write!(f, "{{{self}}}")
+        }
+    }
+}
+impl fmt::Display for TwistLayers {
+    /// Formats 1-indexed layers like HSC, such as `2` or `1-2` or `1,3`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(u8, u8)> = vec![];
+        for i in (0..8).filter(|i| self.0 >> i & 1 != 0) {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == i => *end = i,
+                _ => ranges.push((i, i)),
+            }
+        }
+        let range_strings = ranges.iter().map(|&(start, end)| match start == end {
+            true => format!("{}", start + 1),
+            false => format!("{}-{}", start + 1, end + 1),
+        });
+        write!(f, "{}", range_strings.format(","))
+    }
+}
+impl FromStr for TwistLayers {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mask = 0_u8;
+        for range in s.split(',') {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let parse_layer = |layer: &str| match layer.trim().parse::<u8>() {
+                Ok(n @ 1..=Self::MAX_LAYER_COUNT) => Ok(n - 1),
+                _ => Err("invalid layer"),
+            };
+            for layer in parse_layer(start)?..=parse_layer(end)? {
+                mask |= 1 << layer;
+            }
+        }
+        Self::new(mask).ok_or("invalid layer range")
+    }
+}
 
 impl TransformByElem for Twist {
     #[inline]
     fn transform_by(self, elem: ElemId) -> Self {
-        Twist {
-            grip: elem * self.grip,
-            transform: elem.transform(self.transform),
-        }
+        Twist::new(elem * self.grip(), elem.transform(self.transform)).with_layers(self.layers())
     }
 }
 
@@ -80,22 +250,10 @@ fn twist_names_3d() -> HashMap<Twist, String> {
 }
 
 fn twist_names_4d() -> HashMap<Twist, String> {
-    let iu = Twist {
-        grip: I,
-        transform: XZ,
-    };
-    let iu2 = Twist {
-        grip: I,
-        transform: XZ * XZ,
-    };
-    let iur = Twist {
-        grip: I,
-        transform: YX * XZ * XZ,
-    };
-    let iurf = Twist {
-        grip: I,
-        transform: ZY * YX,
-    };
+    let iu = Twist::new(I, XZ);
+    let iu2 = Twist::new(I, XZ * XZ);
+    let iur = Twist::new(I, YX * XZ * XZ);
+    let iurf = Twist::new(I, ZY * YX);
 
     let mut ret = HashMap::new();
     for offset in *HYPERCUBE_ROTATIONS {
@@ -116,6 +274,57 @@ fn twist_names_4d() -> HashMap<Twist, String> {
     ret
 }
 
+/// Returns known twist names similar to `name`, for suggesting corrections to
+/// a name that isn't known.
+///
+/// 4D twist names list the grips after the first in the order used in HSC1
+/// log files, so if there are names that differ from `name` only in that
+/// order or in case, such as `IUR` for `IRU`, only those are returned.
+/// Otherwise, names one character away from `name` are returned.
+pub fn similar_twist_names(name: &str) -> Vec<String> {
+    let name = name.to_ascii_uppercase();
+    // The first character is the grip being twisted, which must match.
+    let key = |s: &str| {
+        (
+            s.chars().next(),
+            s.chars().skip(1).sorted().collect::<String>(),
+        )
+    };
+    let name_key = key(&name);
+    let reordered = (TWISTS_FROM_NAME.keys())
+        .filter(|other| key(other) == name_key)
+        .cloned()
+        .sorted()
+        .collect_vec();
+    if !reordered.is_empty() {
+        return reordered;
+    }
+    (TWISTS_FROM_NAME.keys())
+        .filter(|other| is_one_edit_apart(&name, other))
+        .cloned()
+        .sorted()
+        .collect()
+}
+
+/// Returns whether `b` can be made from `a` by inserting, deleting, or
+/// replacing exactly one character.
+fn is_one_edit_apart(a: &str, b: &str) -> bool {
+    let (a, b) = (a.chars().collect_vec(), b.chars().collect_vec());
+    let (short, long) = if a.len() <= b.len() {
+        (&a, &b)
+    } else {
+        (&b, &a)
+    };
+    let prefix = std::iter::zip(short, long)
+        .take_while(|(x, y)| x == y)
+        .count();
+    match long.len() - short.len() {
+        0 => prefix < short.len() && short[prefix + 1..] == long[prefix + 1..],
+        1 => short[prefix..] == long[prefix + 1..],
+        _ => false,
+    }
+}
+
 /// Sort a list of unique grips according to the order used in HSC1 log files.
 fn hsc1_sort<const N: usize>(grips: [GripId; N]) -> [GripId; N] {
     [U, D, F, B, R, L, O, I]
@@ -124,3 +333,54 @@ fn hsc1_sort<const N: usize>(grips: [GripId; N]) -> [GripId; N] {
         .collect_array()
         .expect("duplicate grips in twist name")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_twist_layers_notation() {
+        for (s, mask) in [("1", 0b001), ("2", 0b010), ("1-2", 0b011), ("1,3", 0b101)] {
+            let layers = TwistLayers::new(mask).unwrap();
+            assert_eq!(layers.to_string(), s);
+            assert_eq!(s.parse(), Ok(layers));
+        }
+        assert_eq!("1-3,5".parse(), Ok(TwistLayers(0b10111)));
+        assert!("0".parse::<TwistLayers>().is_err());
+        assert!("2-1".parse::<TwistLayers>().is_err());
+        assert!("6".parse::<TwistLayers>().is_err());
+        assert_eq!(TwistLayers::new(0b100000), None);
+
+        let twist = TWISTS_FROM_NAME["IU"];
+        assert_eq!(twist.with_layers(TwistLayers::WIDE).to_string(), "{1-2}IU");
+        assert_eq!("{2}IU".parse(), Ok(twist.with_layers(TwistLayers::MIDDLE)));
+        assert_eq!("{1}IU".parse(), Ok(twist));
+        assert!("{2IU".parse::<Twist>().is_err());
+        assert_eq!("{1-3}IU".parse(), Ok(twist.with_layers(TwistLayers::ALL)));
+        assert!("{4}IU".parse::<Twist>().is_err());
+        assert!("{1,4}IU".parse::<Twist>().is_err());
+
+        // Layers are stored next to the grip.
+        for &twist in TWISTS_FROM_NAME.values() {
+            for mask in 1..1 << TwistLayers::MAX_LAYER_COUNT {
+                let layers = TwistLayers::new(mask).unwrap();
+                let layered = twist.with_layers(layers);
+                assert_eq!(
+                    (layered.grip(), layered.transform),
+                    (twist.grip(), twist.transform)
+                );
+                assert_eq!(layered.layers(), layers);
+            }
+        }
+        assert_eq!(std::mem::size_of::<Twist>(), 2);
+    }
+
+    #[test]
+    fn test_similar_twist_names() {
+        assert_eq!(similar_twist_names("IRU"), ["IUR"]);
+        assert_eq!(similar_twist_names("iu"), ["IU"]);
+        assert_eq!(similar_twist_names("IRUF"), ["IUFR"]);
+        assert!(similar_twist_names("IX").contains(&"IU".to_owned()));
+        assert!(similar_twist_names("IUFX2").is_empty());
+    }
+}
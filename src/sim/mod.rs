@@ -1,9 +1,13 @@
+pub mod analysis;
 pub mod blockbuilding;
 pub mod common;
 mod puzzle;
 pub mod puzzle_state;
+pub mod stickers;
 
+pub use analysis::*;
 pub use blockbuilding::*;
 pub use common::*;
 pub use puzzle::*;
 pub use puzzle_state::*;
+pub use stickers::*;
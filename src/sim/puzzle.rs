@@ -9,6 +9,40 @@ use crate::sim::common::*;
 #[static_init::dynamic]
 pub static RUBIKS_4D: Puzzle = Puzzle::new(4, HYPERCUBE_GRIPS, &*HYPERCUBE_ROTATIONS);
 
+/// 3x3x3x3 facet-turning twisty puzzle with slice and wide twists.
+///
+/// Twists of the middle slice are only listed for positive grips, since they
+/// are the same as inverse twists of the opposite grip.
+#[static_init::dynamic]
+pub static RUBIKS_4D_WITH_SLICES: Puzzle =
+    Puzzle::new(4, HYPERCUBE_GRIPS, &*HYPERCUBE_ROTATIONS).with_slice_twists();
+
+/// 3x3x3x3 facet-turning twisty puzzle with rotations of the whole puzzle as
+/// moves. Only the 74 rotations that fix a grip are moves, and the other 117
+/// take two of them.
+#[static_init::dynamic]
+pub static RUBIKS_4D_WITH_ROTATIONS: Puzzle =
+    Puzzle::new(4, HYPERCUBE_GRIPS, &*HYPERCUBE_ROTATIONS).with_rotation_twists();
+
+/// 3x3x3x3 facet-turning twisty puzzle with slice and wide twists and with
+/// rotations of the whole puzzle as moves.
+#[static_init::dynamic]
+pub static RUBIKS_4D_WITH_SLICES_AND_ROTATIONS: Puzzle =
+    Puzzle::new(4, HYPERCUBE_GRIPS, &*HYPERCUBE_ROTATIONS)
+        .with_slice_twists()
+        .with_rotation_twists();
+
+/// Returns the 3x3x3x3 with the given kinds of moves in addition to
+/// outer-layer twists.
+pub fn rubiks_4d(slice_twists: bool, rotation_twists: bool) -> &'static Puzzle {
+    match (slice_twists, rotation_twists) {
+        (false, false) => &RUBIKS_4D,
+        (true, false) => &RUBIKS_4D_WITH_SLICES,
+        (false, true) => &RUBIKS_4D_WITH_ROTATIONS,
+        (true, true) => &RUBIKS_4D_WITH_SLICES_AND_ROTATIONS,
+    }
+}
+
 /// 3x3x3 face-turning twisty puzzle.
 #[static_init::dynamic]
 pub static RUBIKS_3D: Puzzle = Puzzle::new(3, CUBE_GRIPS, &*CUBE_ROTATIONS);
@@ -23,32 +57,79 @@ pub struct Puzzle {
     /// Flattened list of twists. This contains the same information as
     /// [`Self::grips`], but flattened for easy enumeration and random sampling.
     pub twists: Vec<Twist>,
+    /// Rotations of the whole puzzle, which are the symmetries of the puzzle.
+    pub rotations: Vec<ElemId>,
+    /// Whether the puzzle has slice and wide twists. If so, blocks may be
+    /// merged even if they are not connected, because a slice twist can
+    /// connect them later.
+    pub slice_twists: bool,
+    /// Whether the puzzle has twists of all layers, which rotate the whole
+    /// puzzle. See [`Twist::is_rotation()`].
+    pub rotation_twists: bool,
 }
 impl Puzzle {
+    /// Number of layers along each axis of every puzzle.
+    pub const LAYER_COUNT: u8 = 3;
+
     pub fn new(ndim: usize, grips: impl IntoIterator<Item = GripId>, group: &[ElemId]) -> Self {
         let grips = grips
             .into_iter()
-            .map(|id| GripData {
-                id,
-                transforms: id.transforms(group),
-            })
+            .map(|id| GripData::new(id, id.transforms(group), vec![TwistLayers::OUTER]))
             .collect_vec();
 
-        let twists = grips
-            .iter()
-            .flat_map(|grip| {
-                grip.transforms.iter().map(|&transform| Twist {
-                    grip: grip.id,
-                    transform,
-                })
-            })
-            .collect_vec();
+        let twists = grips.iter().flat_map(|grip| grip.twists()).collect_vec();
 
         Self {
             ndim,
             grips,
             twists,
+            rotations: group.to_vec(),
+            slice_twists: false,
+            rotation_twists: false,
+        }
+    }
+
+    /// Adds slice twists on positive grips and wide twists on all grips.
+    fn with_slice_twists(mut self) -> Self {
+        for grip in &mut self.grips {
+            if grip.id.signum() > 0 {
+                grip.layers.push(TwistLayers::MIDDLE);
+            }
+            grip.layers.push(TwistLayers::WIDE);
         }
+        self.slice_twists = true;
+        self.update_twists()
+    }
+
+    /// Adds rotations of the whole puzzle on positive grips. Each rotation is
+    /// only listed once, on the first positive grip that it fixes.
+    ///
+    /// Only the 74 rotations that fix a grip are added, which are the ones that
+    /// MC4D can do with one click. The other 117 rotations fix no grip, so
+    /// they take two rotations, and the search never tries two in a row.
+    fn with_rotation_twists(mut self) -> Self {
+        for grip in &mut self.grips {
+            if grip.id.signum() > 0 {
+                grip.layers.push(TwistLayers::ALL);
+            }
+        }
+        self.rotation_twists = true;
+        self.update_twists()
+    }
+
+    fn update_twists(mut self) -> Self {
+        let grips = std::mem::take(&mut self.grips);
+        self.grips = (grips.into_iter())
+            .map(|grip| GripData::new(grip.id, grip.transforms, grip.layers))
+            .collect();
+        self.twists = self.grips.iter().flat_map(|grip| grip.twists()).collect();
+        self
+    }
+
+    /// Returns whether every twist is of a single outer layer, which the twist
+    /// sequence automaton assumes.
+    pub fn has_only_outer_twists(&self) -> bool {
+        !self.slice_twists && !self.rotation_twists
     }
 
     pub fn grip_set(&self) -> GripSet {
@@ -66,18 +147,67 @@ pub struct GripData {
     pub id: GripId,
     /// Elements from the grip group that fix this grip.
     pub transforms: Vec<ElemId>,
+    /// Layers that can be twisted from this grip.
+    pub layers: Vec<TwistLayers>,
+    /// Twists of each layer by each transform, except for rotations that are
+    /// listed on an earlier grip.
+    twists: Vec<Twist>,
 }
 impl GripData {
+    fn new(id: GripId, transforms: Vec<ElemId>, layers: Vec<TwistLayers>) -> Self {
+        let is_listed_on_earlier_grip = |t: ElemId| {
+            (0..id.id())
+                .map(GripId::new)
+                .any(|g| g.signum() > 0 && t * g == g)
+        };
+        let twists = itertools::iproduct!(&layers, &transforms)
+            .map(|(&layers, &transform)| Twist::new(id, transform).with_layers(layers))
+            .filter(|twist| !twist.is_rotation() || !is_listed_on_earlier_grip(twist.transform))
+            .collect();
+        Self {
+            id,
+            transforms,
+            layers,
+            twists,
+        }
+    }
+
     pub fn twists(&self) -> impl Iterator<Item = Twist> {
-        let grip = self.id;
-        self.transforms
-            .iter()
-            .map(move |&transform| Twist { grip, transform })
+        self.twists.iter().copied()
     }
     pub fn par_twists(&self) -> impl ParallelIterator<Item = Twist> {
-        let grip = self.id;
-        self.transforms
-            .par_iter()
-            .map(move |&transform| Twist { grip, transform })
+        self.twists.par_iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_twists() {
+        let rotations = (RUBIKS_4D_WITH_ROTATIONS.twists.iter())
+            .filter(|twist| twist.is_rotation())
+            .map(|twist| twist.transform)
+            .collect_vec();
+        assert!(rotations.iter().all_unique());
+
+        let fixes_grip = |elem: ElemId| HYPERCUBE_GRIPS.iter().any(|&g| elem * g == g);
+        let expected = (HYPERCUBE_ROTATIONS.iter().copied())
+            .filter(|&elem| elem != IDENT && fixes_grip(elem))
+            .sorted()
+            .collect_vec();
+        assert_eq!(rotations.iter().copied().sorted().collect_vec(), expected);
+        assert_eq!(rotations.len(), 74);
+
+        // Every other rotation is two rotations.
+        let unlisted = (HYPERCUBE_ROTATIONS.iter().copied())
+            .filter(|&elem| !fixes_grip(elem))
+            .collect_vec();
+        assert_eq!(unlisted.len(), 117);
+        for elem in unlisted {
+            let is_product = |&a: &ElemId| rotations.iter().any(|&b| a * b == elem);
+            assert!(rotations.iter().any(is_product), "{elem}");
+        }
     }
 }
@@ -1,5 +1,7 @@
 use cgmath::{InnerSpace, vec4};
 use itertools::Itertools;
+use rand::Rng;
+use rand::seq::{IndexedRandom, SliceRandom};
 
 use crate::{ELEM_COUNT, ElemId, GripId, HYPERCUBE_ROTATIONS, IDENT, Twist, Vec4};
 
@@ -14,6 +16,38 @@ const INDICES_FOR_GRIP: [[u8; 26]; 8] = [
     indices_for_grip(GripId::new(7)),
 ];
 
+/// Indices of the pieces in each layer, counting inward from each grip.
+///
+/// Like [`indices_for_grip()`], the outer layer of a positive grip is at
+/// coordinate -1.
+#[static_init::dynamic]
+static INDICES_FOR_GRIP_LAYER: [[Vec<u8>; 3]; 8] = std::array::from_fn(|g| {
+    let g = GripId::new(g as u8);
+    std::array::from_fn(|layer| {
+        itertools::iproduct!(-1..=1, -1..=1, -1..=1, -1..=1)
+            .map(|(w, z, y, x)| vec4(x, y, z, w))
+            .filter(|v| v[g.axis()] * g.signum() == layer as i8 - 1)
+            .filter_map(vec4_to_index)
+            .collect()
+    })
+});
+
+/// Position of the piece at each index.
+#[static_init::dynamic]
+static PIECE_POSITIONS: [Vec4; 72] = itertools::iproduct!(-1..=1, -1..=1, -1..=1, -1..=1)
+    .map(|(w, z, y, x)| vec4(x, y, z, w))
+    .filter(|&v| vec4_to_index(v).is_some())
+    .collect_array()
+    .unwrap();
+
+/// Indices of the ridges (2c), edges (3c), and corners (4c).
+#[static_init::dynamic]
+pub(crate) static INDICES_BY_PIECE_TYPE: [Vec<usize>; 3] = [2, 3, 4].map(|sticker_count| {
+    (0..72)
+        .filter(|&i| sticker_axes(piece_position(i)).count() == sticker_count)
+        .collect()
+});
+
 #[static_init::dynamic]
 static MUL_ELEM_INDEX: [[u8; 72]; ELEM_COUNT] = gen_mul_elem_index_table();
 
@@ -33,11 +67,45 @@ impl Default for PuzzleState {
     }
 }
 impl PuzzleState {
+    /// Returns the state produced by applying `twists` to a solved puzzle.
+    pub fn from_twists(twists: &[Twist]) -> Self {
+        let mut ret = Self::default();
+        ret.do_twists(twists);
+        ret
+    }
+
+    /// Constructs a state from the attitude of the piece at each index. See
+    /// [`piece_position()`].
+    pub(crate) fn from_piece_attitudes(piece_attitudes: [ElemId; 72]) -> Self {
+        Self { piece_attitudes }
+    }
+    /// Returns the attitude of the piece at each index, which is the rotation
+    /// that took it there from its solved position. See [`piece_position()`].
+    pub fn piece_attitudes(&self) -> &[ElemId; 72] {
+        &self.piece_attitudes
+    }
+
+    /// Returns the current index of the piece whose solved position is
+    /// `solved_pos`. See [`piece_position()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no piece at `solved_pos`.
+    pub fn find_piece(&self, solved_pos: Vec4) -> usize {
+        (self.piece_attitudes.iter().enumerate())
+            .position(|(index, &attitude)| attitude.inv() * piece_position(index) == solved_pos)
+            .expect("no piece at position")
+    }
+
     pub fn do_twist(&mut self, twist: Twist) {
         let mut ret = self.clone();
 
+        let layers = (0..3).filter(|layer| twist.layers().mask() >> layer & 1 != 0);
+        let indices =
+            layers.flat_map(|layer| &INDICES_FOR_GRIP_LAYER[twist.grip().id() as usize][layer]);
+
         // permute & reorient
-        for old_index in INDICES_FOR_GRIP[twist.grip.id() as usize] {
+        for &old_index in indices {
             let new_index = MUL_ELEM_INDEX[twist.transform.id() as usize][old_index as usize];
             ret.piece_attitudes[new_index as usize] =
                 twist.transform * self.piece_attitudes[old_index as usize];
@@ -50,6 +118,156 @@ impl PuzzleState {
             self.do_twist(twist);
         }
     }
+    /// Rotates the whole puzzle.
+    pub fn rotate(&mut self, rotation: ElemId) {
+        let mut ret = self.clone();
+        for (old_index, &attitude) in self.piece_attitudes.iter().enumerate() {
+            let new_index = MUL_ELEM_INDEX[rotation.id() as usize][old_index];
+            ret.piece_attitudes[new_index as usize] = rotation * attitude;
+        }
+        *self = ret;
+    }
+
+    /// Returns the state produced by applying `self` and then `other` to a
+    /// solved puzzle.
+    pub fn then(&self, other: &Self) -> Self {
+        // `other` moves the piece at each location the same way it moves its
+        // own piece that started there.
+        let mut ret = Self::default();
+        for (index, &attitude) in other.piece_attitudes.iter().enumerate() {
+            let old_index = vec4_to_index(attitude.inv() * PIECE_POSITIONS[index]).unwrap();
+            ret.piece_attitudes[index] = attitude * self.piece_attitudes[old_index as usize];
+        }
+        ret
+    }
+
+    /// Returns the state that undoes `self`, so that `self.then(&inverse)` is
+    /// solved.
+    pub fn inverse(&self) -> Self {
+        let mut ret = Self::default();
+        for (index, &attitude) in self.piece_attitudes.iter().enumerate() {
+            let old_index = vec4_to_index(attitude.inv() * PIECE_POSITIONS[index]).unwrap();
+            ret.piece_attitudes[old_index as usize] = attitude.inv();
+        }
+        ret
+    }
+
+    /// Returns whether every sticker is on the face of its color.
+    ///
+    /// This is looser than comparing to [`PuzzleState::default()`], because
+    /// some pieces can be rotated without moving any of their stickers.
+    pub fn is_solved(&self) -> bool {
+        self.is_solved_relative_to(IDENT)
+    }
+
+    /// Returns whether every sticker is on the same face as the others of its
+    /// color, allowing the whole puzzle to be rotated.
+    pub fn is_solved_up_to_rotation(&self) -> bool {
+        // The piece at index 0 is a corner, which has a sticker on every axis,
+        // so in a solved puzzle its attitude is the rotation of the whole
+        // puzzle.
+        self.is_solved_relative_to(self.piece_attitudes[0])
+    }
+
+    fn is_solved_relative_to(&self, rotation: ElemId) -> bool {
+        let inv_rotation = rotation.inv();
+        (self.piece_attitudes.iter().enumerate()).all(|(index, &attitude)| {
+            let original_pos = attitude.inv() * PIECE_POSITIONS[index];
+            let relative_attitude = inv_rotation * attitude;
+            // Each nonzero coordinate is a sticker.
+            (0..4).all(|axis| {
+                let mut sticker = vec4(0, 0, 0, 0);
+                sticker[axis] = original_pos[axis];
+                relative_attitude * sticker == sticker
+            })
+        })
+    }
+
+    /// Returns whether `self` and `other` are the same state, possibly with
+    /// the whole puzzle rotated by one of [`HYPERCUBE_ROTATIONS`].
+    pub fn eq_up_to_rotation(&self, other: &Self) -> bool {
+        HYPERCUBE_ROTATIONS.iter().any(|&rotation| {
+            let mut rotated = self.clone();
+            rotated.rotate(rotation);
+            rotated == *other
+        })
+    }
+
+    /// Returns a uniformly random state out of all the states that can be
+    /// reached by twisting a solved puzzle.
+    ///
+    /// Unlike a sequence of random twists, every reachable state is equally
+    /// likely.
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let mut piece_originals = INDICES_BY_PIECE_TYPE.clone();
+        for originals in &mut piece_originals {
+            originals.shuffle(rng);
+        }
+        let [ridges, edges, corners] = &mut piece_originals;
+        if is_odd_permutation(corners) {
+            corners.swap(0, 1);
+        }
+        if is_odd_permutation(ridges) != is_odd_permutation(edges) {
+            edges.swap(0, 1);
+        }
+
+        let mut piece_attitudes = [IDENT; 72];
+        for (indices, originals) in std::iter::zip(&*INDICES_BY_PIECE_TYPE, &piece_originals) {
+            let mut total_orientation = 0;
+            for (&index, &original) in std::iter::zip(indices, originals) {
+                let is_last = index == *indices.last().unwrap();
+                let attitudes = HYPERCUBE_ROTATIONS
+                    .iter()
+                    .copied()
+                    .filter(|&r| r * piece_position(original) == piece_position(index))
+                    .filter(|&r| {
+                        let total = total_orientation + piece_orientation(index, r);
+                        !is_last || total.is_multiple_of(orientation_modulus(index))
+                    })
+                    .collect_vec();
+                let attitude = *attitudes.choose(rng).unwrap();
+                total_orientation += piece_orientation(index, attitude);
+                piece_attitudes[index] = attitude;
+            }
+        }
+        Self { piece_attitudes }
+    }
+
+    /// Returns whether the state can be reached by twisting a solved puzzle.
+    ///
+    /// Corners must have an even permutation, and ridges and edges must have
+    /// permutations of the same parity. The orientations of the ridges and
+    /// edges must each add up to 0 mod 2, and the orientations of the corners
+    /// must add up to 0 mod 3. See [`piece_orientation()`].
+    pub fn is_solvable(&self) -> bool {
+        let [ridges, edges, corners] =
+            (INDICES_BY_PIECE_TYPE.each_ref()).map(|indices| self.piece_originals(indices));
+        let (Some(ridges), Some(edges), Some(corners)) = (ridges, edges, corners) else {
+            return false;
+        };
+        let is_orientation_valid = INDICES_BY_PIECE_TYPE.iter().all(|indices| {
+            let total: u8 = indices
+                .iter()
+                .map(|&i| piece_orientation(i, self.piece_attitudes[i]))
+                .sum();
+            total.is_multiple_of(orientation_modulus(indices[0]))
+        });
+        !is_odd_permutation(&corners)
+            && is_odd_permutation(&ridges) == is_odd_permutation(&edges)
+            && is_orientation_valid
+    }
+
+    /// Returns the original index of the piece at each of `indices`, or `None`
+    /// if they aren't a permutation of `indices`.
+    fn piece_originals(&self, indices: &[usize]) -> Option<Vec<usize>> {
+        let originals: Vec<usize> = indices
+            .iter()
+            .map(|&i| piece_index(self.piece_attitudes[i].inv() * piece_position(i)))
+            .collect::<Option<_>>()?;
+        let is_permutation = originals.iter().sorted().eq(indices);
+        is_permutation.then_some(originals)
+    }
+
     pub fn unoriented_pieces(&self, last_layer: GripId) -> [usize; 3] {
         let piece_indices = INDICES_FOR_GRIP[last_layer.id() as usize];
         let is_piece_unoriented = |&i: &usize| {
@@ -73,6 +291,80 @@ impl PuzzleState {
     }
 }
 
+/// Returns the position of the piece at `index` in a [`PuzzleState`], with
+/// each coordinate from -1 to 1 in the direction of [`GripId::vec()`].
+///
+/// # Panics
+///
+/// Panics if `index` is out of range (must be strictly less than 72).
+pub fn piece_position(index: usize) -> Vec4 {
+    // Internally, the outer layer of a positive grip is at coordinate -1.
+    -PIECE_POSITIONS[index]
+}
+/// Returns the index of the piece at `pos` in a [`PuzzleState`], or `None` if
+/// there is no piece there. This is the inverse of [`piece_position()`].
+pub fn piece_index(pos: Vec4) -> Option<usize> {
+    vec4_to_index(-pos).map(usize::from)
+}
+
+/// Returns the axes that the piece at `pos` has stickers on, in order.
+fn sticker_axes(pos: Vec4) -> impl Iterator<Item = usize> {
+    (0..4).filter(move |&axis| pos[axis] != 0)
+}
+
+/// Returns the orientation of the piece at `index` with `attitude`, which is
+/// an integer mod [`orientation_modulus()`]. In every solvable state, the
+/// orientations of each type of piece add up to 0.
+///
+/// Ridges and edges have orientation 1 if their stickers are in an odd
+/// permutation of the order they were in. For corners, that's decided by
+/// their position, so their orientation is how the permutation acts on the 3
+/// ways to split the 4 stickers into pairs.
+pub(crate) fn piece_orientation(index: usize, attitude: ElemId) -> u8 {
+    let pos = piece_position(index);
+    let original_pos = attitude.inv() * pos;
+    // Index into the stickers at `pos` of where each sticker went.
+    let mut sticker_permutation = sticker_axes(original_pos)
+        .map(|axis| {
+            let mut sticker = vec4(0, 0, 0, 0);
+            sticker[axis] = original_pos[axis];
+            let moved = attitude * sticker;
+            sticker_axes(pos).position(|axis| moved[axis] != 0).unwrap()
+        })
+        .collect_vec();
+    if sticker_permutation.len() < 4 {
+        return is_odd_permutation(&sticker_permutation) as u8;
+    }
+
+    // Corners with an odd number of negative coordinates are mirror images
+    // of the others, so swap two stickers to make the permutation even.
+    if sticker_axes(pos).filter(|&axis| pos[axis] < 0).count() % 2 == 1 {
+        for i in &mut sticker_permutation {
+            if *i < 2 {
+                *i ^= 1;
+            }
+        }
+    }
+    if is_odd_permutation(&sticker_permutation) {
+        sticker_permutation.swap(0, 1);
+    }
+    // The pairing {01, 23} goes to the one where 0 is paired with 1, 2, or 3.
+    let i = sticker_permutation.iter().position(|&i| i == 0).unwrap();
+    sticker_permutation[i ^ 1] as u8 - 1
+}
+pub(crate) fn orientation_modulus(index: usize) -> u8 {
+    match sticker_axes(piece_position(index)).count() {
+        4 => 3,
+        _ => 2,
+    }
+}
+
+/// Returns whether `values` are an odd permutation of their sorted order.
+fn is_odd_permutation(values: &[usize]) -> bool {
+    let inversions = values.iter().tuple_combinations().filter(|(a, b)| a > b);
+    inversions.count() % 2 == 1
+}
+
 const fn indices_for_grip(g: GripId) -> [u8; 26] {
     let mut strides = [1, 3, 9, 27];
     strides.swap(g.axis(), 0);
@@ -141,6 +433,8 @@ const fn adjust_index(mut i: usize) -> Option<u8> {
 mod tests {
     use std::collections::HashSet;
 
+    use rand::SeedableRng;
+
     use super::*;
     use crate::HYPERCUBE_GRIPS;
 
@@ -159,6 +453,129 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_slice_twist() {
+        // Twisting every layer the same way rotates the whole puzzle, so every
+        // piece has the same attitude.
+        for &twist in &crate::RUBIKS_4D.twists {
+            let mut state = PuzzleState::default();
+            state.do_twist(twist.with_layers(crate::TwistLayers::MIDDLE));
+            assert_ne!(state, PuzzleState::default(), "{twist}");
+            state.do_twist(twist);
+            state.do_twist(crate::Twist::new(twist.grip().opposite(), twist.transform));
+            assert_eq!(state.piece_attitudes, [twist.transform; 72], "{twist}");
+        }
+    }
+
+    #[test]
+    fn test_state_algebra() {
+        let twists = crate::parse_twists("R U R' U R U2 R' IF OU2");
+        let state = PuzzleState::from_twists(&twists);
+        assert!(!state.is_solved());
+        assert!(state.then(&state.inverse()).is_solved());
+        assert!(state.inverse().then(&state).is_solved());
+
+        let inverse_twists = twists.iter().rev().map(|t| t.inv()).collect_vec();
+        assert_eq!(state.inverse(), PuzzleState::from_twists(&inverse_twists));
+
+        let (a, b) = twists.split_at(4);
+        let composed = PuzzleState::from_twists(a).then(&PuzzleState::from_twists(b));
+        assert_eq!(composed, state);
+    }
+
+    #[test]
+    fn test_rotation_equivalence() {
+        let state = PuzzleState::from_twists(&crate::parse_twists("R U R' U'"));
+        for &rotation in HYPERCUBE_ROTATIONS.iter() {
+            let mut rotated = state.clone();
+            rotated.rotate(rotation);
+            assert!(rotated.eq_up_to_rotation(&state));
+
+            let mut solved = PuzzleState::default();
+            solved.rotate(rotation);
+            assert!(solved.is_solved_up_to_rotation());
+            assert_eq!(solved.is_solved(), rotation == IDENT);
+        }
+        let other = PuzzleState::from_twists(&crate::parse_twists("R U R' U"));
+        assert!(!state.eq_up_to_rotation(&other));
+
+        // Spinning a 2c piece in place leaves its stickers alone.
+        let spin = *HYPERCUBE_ROTATIONS
+            .iter()
+            .find(|&&r| {
+                r != IDENT
+                    && r * vec4(1, 0, 0, 0) == vec4(1, 0, 0, 0)
+                    && r * vec4(0, 1, 0, 0) == vec4(0, 1, 0, 0)
+            })
+            .unwrap();
+        let mut state = PuzzleState::default();
+        state.piece_attitudes[vec4_to_index(vec4(1, 1, 0, 0)).unwrap() as usize] = spin;
+        assert!(state.is_solved());
+        assert!(!state.eq_up_to_rotation(&PuzzleState::default()));
+    }
+
+    /// Returns the solved state with the piece at each of `indices` moved to
+    /// the next one, wrapping around, without changing any orientations.
+    fn cycle_pieces(indices: &[usize]) -> PuzzleState {
+        let mut piece_attitudes = *PuzzleState::default().piece_attitudes();
+        for (&from, &to) in indices.iter().circular_tuple_windows() {
+            piece_attitudes[to] = *HYPERCUBE_ROTATIONS
+                .iter()
+                .find(|&&r| {
+                    r * piece_position(from) == piece_position(to) && piece_orientation(to, r) == 0
+                })
+                .unwrap();
+        }
+        PuzzleState::from_piece_attitudes(piece_attitudes)
+    }
+
+    #[test]
+    fn test_solvable_states() {
+        let mut rng = rand_pcg::Pcg64Mcg::seed_from_u64(0);
+        assert!(PuzzleState::default().is_solvable());
+        for _ in 0..20 {
+            let scramble = crate::RUBIKS_4D.random_moves(&mut rng, 50);
+            assert!(PuzzleState::from_twists(&scramble).is_solvable());
+        }
+
+        // Random states are solvable, and aren't all the same parity.
+        let mut ridge_parities = HashSet::new();
+        for _ in 0..20 {
+            let state = PuzzleState::random(&mut rng);
+            assert!(state.is_solvable());
+            assert!(!state.is_solved());
+            let ridges = state.piece_originals(&INDICES_BY_PIECE_TYPE[0]).unwrap();
+            ridge_parities.insert(is_odd_permutation(&ridges));
+        }
+        assert_eq!(ridge_parities.len(), 2);
+
+        // Reorienting a single piece in place makes the state unsolvable.
+        for indices in &*INDICES_BY_PIECE_TYPE {
+            let index = indices[0];
+            let pos = piece_position(index);
+            let attitudes = HYPERCUBE_ROTATIONS
+                .iter()
+                .filter(|&&r| r * pos == pos && piece_orientation(index, r) != 0);
+            for &attitude in attitudes {
+                let mut state = PuzzleState::default();
+                state.piece_attitudes[index] = attitude;
+                assert!(!state.is_solvable());
+            }
+        }
+
+        let [ridges, edges, corners] = &*INDICES_BY_PIECE_TYPE;
+        for indices in [ridges, edges, corners] {
+            // Swapping a single pair of pieces makes the state unsolvable.
+            assert!(!cycle_pieces(&indices[..2]).is_solvable());
+            // So does any other odd permutation of one type of piece.
+            assert!(!cycle_pieces(&indices[..4]).is_solvable());
+        }
+        // Swapping a pair of ridges and a pair of edges together is fine.
+        let swaps = cycle_pieces(&ridges[..2]).then(&cycle_pieces(&edges[..2]));
+        assert!(swaps.is_solvable());
+        assert!(cycle_pieces(&corners[..3]).is_solvable());
+    }
+
     #[test]
     fn test_unoriented_pieces() {
         let mut state = PuzzleState::default();
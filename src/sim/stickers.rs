@@ -0,0 +1,309 @@
+use std::fmt;
+use std::str::FromStr;
+
+use cgmath::vec4;
+use itertools::Itertools;
+
+use crate::{
+    ElemId, GripId, HYPERCUBE_GRIPS, HYPERCUBE_ROTATIONS, PuzzleState, Vec4, piece_index,
+    piece_position,
+};
+
+/// Number of stickers on each cell, including the center.
+pub const STICKERS_PER_CELL: usize = 27;
+
+/// Index of the center sticker on each cell.
+const CENTER_STICKER: usize = 13;
+
+/// Colors of the stickers on a 3x3x3x3, as seen on a physical or virtual
+/// puzzle.
+///
+/// Each color is named by the grip whose cell has that color when the puzzle
+/// is solved. Stickers on each cell are indexed by the coordinates of their
+/// piece along the other three axes, in the order X, Y, Z, W with the first
+/// changing fastest, and each coordinate going from -1 to 1 in the direction of
+/// [`GripId::vec()`]. For example, the first sticker on R is on the piece at
+/// `RDBI` and the last is on the piece at `RUFO`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StickerState {
+    colors: [[GripId; STICKERS_PER_CELL]; 8],
+}
+impl Default for StickerState {
+    fn default() -> Self {
+        Self {
+            colors: HYPERCUBE_GRIPS.map(|g| [g; STICKERS_PER_CELL]),
+        }
+    }
+}
+impl From<&PuzzleState> for StickerState {
+    fn from(state: &PuzzleState) -> Self {
+        let colors = HYPERCUBE_GRIPS.map(|cell| {
+            std::array::from_fn(|sticker| {
+                let Some(index) = piece_index(sticker_position(cell, sticker)) else {
+                    return cell; // center
+                };
+                let attitude = state.piece_attitudes()[index];
+                GripId::from_vec(attitude.inv() * cell.vec()).unwrap()
+            })
+        });
+        Self { colors }
+    }
+}
+impl TryFrom<&StickerState> for PuzzleState {
+    type Error = String;
+
+    /// Identifies each piece by its colors and checks that every piece appears
+    /// exactly once.
+    ///
+    /// Pieces with fewer than four stickers can be rotated without moving
+    /// their stickers, so the attitudes in the resulting state are one choice
+    /// out of several that all look the same.
+    fn try_from(stickers: &StickerState) -> Result<Self, Self::Error> {
+        for cell in HYPERCUBE_GRIPS {
+            let center = stickers.color(cell, CENTER_STICKER);
+            if center != cell {
+                return Err(format!("center of {cell} is {center} instead of {cell}"));
+            }
+        }
+
+        let mut seen_at = [None; 72];
+        let mut piece_attitudes = [ElemId::default(); 72];
+        for (index, attitude) in piece_attitudes.iter_mut().enumerate() {
+            let pos = piece_position(index);
+            let name = piece_name(pos);
+            // Pairs of `(cell, color)` for each sticker on the piece.
+            let sticker_colors = sticker_grips(pos)
+                .map(|cell| (cell, stickers.color(cell, sticker_index(cell, pos))))
+                .collect_vec();
+            let colors = sticker_colors.iter().map(|&(_, color)| color).collect_vec();
+            if !colors.iter().map(|c| c.axis()).all_unique() {
+                let colors = colors.iter().join("");
+                return Err(format!(
+                    "piece at {name} has colors {colors} that don't fit together"
+                ));
+            }
+
+            let original_pos = colors.iter().map(|c| c.vec()).sum();
+            let original_index = piece_index(original_pos).unwrap();
+            if let Some(other_pos) = seen_at[original_index].replace(pos) {
+                let other_name = piece_name(other_pos);
+                let piece = piece_name(original_pos);
+                return Err(format!("piece {piece} is at both {other_name} and {name}"));
+            }
+
+            *attitude = *HYPERCUBE_ROTATIONS
+                .iter()
+                .find(|&&r| {
+                    sticker_colors
+                        .iter()
+                        .all(|&(cell, c)| r * c.vec() == cell.vec())
+                })
+                .ok_or_else(|| format!("piece at {name} is a mirror image"))?;
+        }
+
+        Ok(Self::from_piece_attitudes(piece_attitudes))
+    }
+}
+impl StickerState {
+    /// Returns the color of a sticker on `cell`. See [`StickerState`] for how
+    /// stickers are indexed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sticker` is out of range (must be strictly less than 27).
+    pub fn color(&self, cell: GripId, sticker: usize) -> GripId {
+        self.colors[cell.id() as usize][sticker]
+    }
+    /// Sets the color of a sticker on `cell`. See [`StickerState`] for how
+    /// stickers are indexed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sticker` is out of range (must be strictly less than 27).
+    pub fn set_color(&mut self, cell: GripId, sticker: usize, color: GripId) {
+        self.colors[cell.id() as usize][sticker] = color;
+    }
+}
+
+impl fmt::Display for StickerState {
+    /// Writes one line per cell, with the cell name followed by the colors of
+    /// its stickers in groups of 9.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (cell, colors) in HYPERCUBE_GRIPS.iter().zip(&self.colors) {
+            let groups = colors.chunks(9).map(|chunk| chunk.iter().join(""));
+            writeln!(f, "{cell}: {}", groups.format(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StickerState {
+    type Err = String;
+
+    /// Parses the format written by [`StickerState`]'s `Display` impl: one
+    /// `cell: colors` line for each of the 8 cells in any order, where
+    /// `colors` is the 27 colors of the cell's stickers written as grip names.
+    /// Whitespace between colors, blank lines, and `#` comments are ignored.
+    ///
+    /// This only checks the syntax. Convert to a [`PuzzleState`] to check that
+    /// the colors make a valid puzzle.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = Self::default();
+        let mut cells_seen = [false; 8];
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (cell, colors) = line
+                .split_once(':')
+                .ok_or_else(|| format!("expected `cell: colors`, got {line:?}"))?;
+            let cell = parse_grip(cell.trim())?;
+            if std::mem::replace(&mut cells_seen[cell.id() as usize], true) {
+                return Err(format!("colors for {cell} are given twice"));
+            }
+            let colors: Vec<GripId> = colors
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| parse_grip(&c.to_string()))
+                .try_collect()?;
+            ret.colors[cell.id() as usize] = colors.try_into().map_err(|colors: Vec<_>| {
+                let n = colors.len();
+                format!("expected {STICKERS_PER_CELL} colors for {cell}, got {n}")
+            })?;
+        }
+        if let Some(missing) = HYPERCUBE_GRIPS
+            .iter()
+            .find(|g| !cells_seen[g.id() as usize])
+        {
+            return Err(format!("missing colors for {missing}"));
+        }
+        Ok(ret)
+    }
+}
+
+fn parse_grip(s: &str) -> Result<GripId, String> {
+    s.chars()
+        .exactly_one()
+        .ok()
+        .and_then(|c| GripId::from_char(c.to_ascii_uppercase()))
+        .ok_or_else(|| format!("expected one of RLUDFBOI, got {s:?}"))
+}
+
+/// Returns the position of a piece with a sticker on `cell`.
+fn sticker_position(cell: GripId, mut sticker: usize) -> Vec4 {
+    let mut ret = vec4(0, 0, 0, 0);
+    for axis in (0..4).filter(|&axis| axis != cell.axis()) {
+        ret[axis] = (sticker % 3) as i8 - 1;
+        sticker /= 3;
+    }
+    ret[cell.axis()] = cell.signum();
+    ret
+}
+/// Returns the index on `cell` of the sticker on the piece at `pos`. This is
+/// the inverse of [`sticker_position()`].
+fn sticker_index(cell: GripId, pos: Vec4) -> usize {
+    (0..4)
+        .filter(|&axis| axis != cell.axis())
+        .rev()
+        .fold(0, |acc, axis| acc * 3 + (pos[axis] + 1) as usize)
+}
+
+/// Returns the cells that the piece at `pos` has stickers on.
+pub(crate) fn sticker_grips(pos: Vec4) -> impl Iterator<Item = GripId> {
+    HYPERCUBE_GRIPS
+        .into_iter()
+        .filter(move |g| pos[g.axis()] == g.signum())
+}
+
+/// Returns the name of the piece at `pos`, such as `UFR`.
+pub(crate) fn piece_name(pos: Vec4) -> String {
+    sticker_grips(pos).map(|g| g.char()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sticker_round_trip() {
+        let state = PuzzleState::from_twists(&crate::parse_twists("R U R' U R U2 R' IF OU2"));
+        let stickers = StickerState::from(&state);
+        assert_ne!(stickers, StickerState::default());
+        assert_eq!(stickers.to_string().parse(), Ok(stickers.clone()));
+
+        let round_trip = PuzzleState::try_from(&stickers).unwrap();
+        assert_eq!(StickerState::from(&round_trip), stickers);
+        assert!(state.inverse().then(&round_trip).is_solved());
+
+        assert_eq!(
+            StickerState::from(&PuzzleState::default()),
+            StickerState::default()
+        );
+        for cell in HYPERCUBE_GRIPS {
+            for sticker in 0..STICKERS_PER_CELL {
+                let pos = sticker_position(cell, sticker);
+                assert_eq!(sticker_index(cell, pos), sticker);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sticker_twist() {
+        let state = PuzzleState::from_twists(&crate::parse_twists("UF"));
+        let stickers = StickerState::from(&state);
+        let all_stickers = || {
+            HYPERCUBE_GRIPS
+                .into_iter()
+                .flat_map(|cell| (0..STICKERS_PER_CELL).map(move |i| (cell, i)))
+        };
+
+        // Twisting U moves stickers between the cells next to it, but every
+        // sticker on U stays on U and D isn't touched.
+        assert_ne!(stickers, StickerState::default());
+        for cell in [crate::U, crate::D] {
+            assert!((0..STICKERS_PER_CELL).all(|i| stickers.color(cell, i) == cell));
+        }
+        let color_counts = all_stickers().counts_by(|(cell, i)| stickers.color(cell, i));
+        assert!(color_counts.values().all(|&n| n == STICKERS_PER_CELL));
+    }
+
+    #[test]
+    fn test_invalid_stickers() {
+        let solved = StickerState::default();
+
+        let mut swapped_center = solved.clone();
+        swapped_center.set_color(crate::R, CENTER_STICKER, crate::L);
+        assert!(PuzzleState::try_from(&swapped_center).is_err());
+
+        // Both stickers of the ridge at UR are R, which doesn't fit together.
+        let ur = vec4(1, 1, 0, 0);
+        let mut bad_piece = solved.clone();
+        bad_piece.set_color(crate::U, sticker_index(crate::U, ur), crate::R);
+        assert!(PuzzleState::try_from(&bad_piece).is_err());
+
+        // The ridge at UR is recolored to look like the one at UL, which is
+        // still there.
+        let mut duplicate = solved.clone();
+        duplicate.set_color(crate::R, sticker_index(crate::R, ur), crate::L);
+        let err = PuzzleState::try_from(&duplicate).unwrap_err();
+        assert!(err.contains("piece LU"), "{err}");
+
+        // Swapping two stickers of a corner mirrors it.
+        let corner = vec4(1, 1, 1, 1);
+        let mut mirrored = solved.clone();
+        mirrored.set_color(crate::R, sticker_index(crate::R, corner), crate::U);
+        mirrored.set_color(crate::U, sticker_index(crate::U, corner), crate::R);
+        let err = PuzzleState::try_from(&mirrored).unwrap_err();
+        assert!(err.contains("mirror"), "{err}");
+
+        assert!("R: RRR".parse::<StickerState>().is_err());
+        assert!(
+            solved
+                .to_string()
+                .replace("I:", "O:")
+                .parse::<StickerState>()
+                .is_err()
+        );
+    }
+}